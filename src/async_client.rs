@@ -0,0 +1,217 @@
+use crate::client::{
+    black_version, check_status, into_string_outcome, not_found, outcome_from_response,
+    path_options, relabel_outcome, request_headers, request_url, str_encoding, unix_socket_path,
+    FormatOutcome, DEFAULT_URL,
+};
+use crate::encoding::SourceEncoding;
+use crate::error::BlackError;
+use crate::notebook::{cell_options, is_notebook, Notebook, NotebookOutcome};
+use crate::options::FormatOptions;
+use crate::version::BlackVersion;
+use reqwest::header::HeaderMap;
+use reqwest::Client as AsyncClient;
+use std::path::Path;
+
+/// A client for a running blackd server that doesn't block the async runtime it's used from
+#[derive(Debug, Clone)]
+pub struct AsyncBlackdClient {
+    http: AsyncClient,
+    url: String,
+    endpoint: String,
+    headers: HeaderMap,
+}
+
+impl Default for AsyncBlackdClient {
+    fn default() -> Self {
+        AsyncBlackdClient::new(DEFAULT_URL)
+    }
+}
+
+impl AsyncBlackdClient {
+    /// Talk to blackd at `url`, either an `http(s)://` URL or a `unix:///path/to/blackd.sock` socket
+    pub fn new<U: Into<String>>(url: U) -> AsyncBlackdClient {
+        let url = url.into();
+        let http = AsyncBlackdClient::http_client_builder(&url)
+            .build()
+            .expect("failed to build an HTTP client");
+
+        AsyncBlackdClient::with_http_client(http, url)
+    }
+
+    /// A reqwest client builder that can reach `url` (i.e. bound to its unix socket),
+    /// to be configured further (e.g. with TLS certificates) for `with_http_client`
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub fn http_client_builder(url: &str) -> reqwest::ClientBuilder {
+        let builder = AsyncClient::builder();
+
+        #[cfg(unix)]
+        if let Some(socket) = unix_socket_path(url) {
+            return builder.unix_socket(socket);
+        }
+
+        builder
+    }
+
+    /// Use an already configured reqwest client, e.g. one with custom timeouts
+    /// (for `unix://` URLs it has to be bound to the socket already)
+    pub fn with_http_client<U: Into<String>>(http: AsyncClient, url: U) -> AsyncBlackdClient {
+        let url = url.into();
+
+        AsyncBlackdClient {
+            http,
+            endpoint: request_url(&url),
+            url,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Extra headers to send along with every request, e.g. for authentication
+    pub fn with_headers(mut self, headers: HeaderMap) -> AsyncBlackdClient {
+        self.headers = headers;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub async fn format_str(
+        &self,
+        source: &str,
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<String>, BlackError> {
+        let encoding = str_encoding(source);
+
+        self.format_source(source.as_bytes(), &encoding, options, Path::new("-"))
+            .await
+            .map(into_string_outcome)
+    }
+
+    pub async fn format_bytes(
+        &self,
+        source: &[u8],
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let encoding = SourceEncoding::detect(source);
+
+        self.format_source(source, &encoding, options, Path::new("-"))
+            .await
+    }
+
+    /// Format the file at `filepath` without touching it, stub files and notebooks are
+    /// detected by their extension and diffs are labelled with the path (notebook
+    /// cells blackd can't parse are left as they are, see `format_notebook`)
+    pub async fn format_path<P: AsRef<Path>>(
+        &self,
+        filepath: P,
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let filepath = filepath.as_ref();
+
+        match tokio::fs::metadata(filepath).await {
+            Ok(metadata) if metadata.is_file() => {}
+            _ => return Err(not_found(filepath)),
+        }
+
+        let source = tokio::fs::read(filepath)
+            .await
+            .map_err(|err| BlackError::io(filepath, err))?;
+
+        if is_notebook(filepath) {
+            return self
+                .format_notebook(&source, options, filepath)
+                .await
+                .map(|notebook| notebook.outcome);
+        }
+
+        let encoding = SourceEncoding::detect(&source);
+        let options = path_options(filepath, options);
+
+        self.format_source(&source, &encoding, &options, filepath)
+            .await
+            .map(|outcome| relabel_outcome(outcome, filepath))
+    }
+
+    /// Format a Jupyter notebook's code cells one by one, with IPython's magics masked,
+    /// leaving its other cells, outputs and metadata alone. Cells blackd can't parse
+    /// are skipped, and diffs and errors are labelled `filepath:cell_N`.
+    pub async fn format_notebook(
+        &self,
+        source: &[u8],
+        options: &FormatOptions,
+        filepath: &Path,
+    ) -> Result<NotebookOutcome, BlackError> {
+        let mut notebook = Notebook::parse(source, filepath)?;
+        let options = cell_options(options)?;
+
+        for cell in notebook.code_cells(filepath) {
+            let result = self
+                .format_source(
+                    cell.source.as_bytes(),
+                    &str_encoding(&cell.source),
+                    &options,
+                    &cell.label,
+                )
+                .await;
+
+            notebook.record(&cell, result)?;
+        }
+
+        Ok(notebook.into_outcome())
+    }
+
+    /// Ask blackd which black release it's running (without formatting anything),
+    /// `None` if it's too old to say
+    pub async fn probe(&self) -> Result<Option<BlackVersion>, BlackError> {
+        let resp = self
+            .http
+            .post(self.endpoint.as_str())
+            .headers(request_headers(
+                &FormatOptions::new(),
+                &str_encoding(""),
+                &self.headers,
+            ))
+            .body(Vec::new())
+            .send()
+            .await
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?;
+
+        let status = resp.status();
+        let version = black_version(resp.headers());
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?
+            .to_vec();
+
+        check_status(status, body, Path::new(&self.url))?;
+
+        Ok(version)
+    }
+
+    async fn format_source(
+        &self,
+        source: &[u8],
+        encoding: &SourceEncoding,
+        options: &FormatOptions,
+        filepath: &Path,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let resp = self
+            .http
+            .post(self.endpoint.as_str())
+            .headers(request_headers(options, encoding, &self.headers))
+            .body(encoding.strip_bom(source).to_vec())
+            .send()
+            .await
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?;
+
+        let status = resp.status();
+        let body = resp
+            .bytes()
+            .await
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?
+            .to_vec();
+
+        outcome_from_response(status, body, source, encoding, options, filepath)
+    }
+}
@@ -0,0 +1,500 @@
+use crate::encoding::SourceEncoding;
+use crate::error::BlackError;
+use crate::files::read_pyfile;
+use crate::notebook::{cell_options, is_notebook, Notebook, NotebookOutcome};
+use crate::options::{headers_from_options, FormatOptions};
+use crate::version::BlackVersion;
+use reqwest::blocking::Client as BlockingClient;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::StatusCode;
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+
+/// The address blackd listens on when started without any arguments
+pub const DEFAULT_URL: &str = "http://localhost:45484/";
+
+/// What blackd made of a piece of source code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatOutcome<T> {
+    /// the reformatted source
+    Changed(T),
+    /// the source was already well formatted
+    Unchanged,
+    /// a unified diff of the changes blackd would make (only when `FormatOptions::diff` is set)
+    Diff(String),
+}
+
+/// A client for a running blackd server
+#[derive(Debug, Clone)]
+pub struct BlackdClient {
+    http: BlockingClient,
+    url: String,
+    endpoint: String,
+    headers: HeaderMap,
+}
+
+impl Default for BlackdClient {
+    fn default() -> Self {
+        BlackdClient::new(DEFAULT_URL)
+    }
+}
+
+impl BlackdClient {
+    /// Talk to blackd at `url`, either an `http(s)://` URL or a `unix:///path/to/blackd.sock` socket
+    pub fn new<U: Into<String>>(url: U) -> BlackdClient {
+        let url = url.into();
+        let http = BlackdClient::http_client_builder(&url)
+            .build()
+            .expect("failed to build an HTTP client");
+
+        BlackdClient::with_http_client(http, url)
+    }
+
+    /// A reqwest client builder that can reach `url` (i.e. bound to its unix socket),
+    /// to be configured further (e.g. with TLS certificates) for `with_http_client`
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    pub fn http_client_builder(url: &str) -> reqwest::blocking::ClientBuilder {
+        let builder = BlockingClient::builder();
+
+        #[cfg(unix)]
+        if let Some(socket) = unix_socket_path(url) {
+            return builder.unix_socket(socket);
+        }
+
+        builder
+    }
+
+    /// Use an already configured reqwest client, e.g. one with custom timeouts
+    /// (for `unix://` URLs it has to be bound to the socket already)
+    pub fn with_http_client<U: Into<String>>(http: BlockingClient, url: U) -> BlackdClient {
+        let url = url.into();
+
+        BlackdClient {
+            http,
+            endpoint: request_url(&url),
+            url,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Extra headers to send along with every request, e.g. for authentication
+    pub fn with_headers(mut self, headers: HeaderMap) -> BlackdClient {
+        self.headers = headers;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn format_str(
+        &self,
+        source: &str,
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<String>, BlackError> {
+        let encoding = str_encoding(source);
+
+        self.format_source(source.as_bytes(), &encoding, options, Path::new("-"))
+            .map(into_string_outcome)
+    }
+
+    pub fn format_bytes(
+        &self,
+        source: &[u8],
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let encoding = SourceEncoding::detect(source);
+
+        self.format_source(source, &encoding, options, Path::new("-"))
+    }
+
+    /// Format the file at `filepath` without touching it, stub files and notebooks are
+    /// detected by their extension and diffs are labelled with the path (notebook
+    /// cells blackd can't parse are left as they are, see `format_notebook`)
+    pub fn format_path<P: AsRef<Path>>(
+        &self,
+        filepath: P,
+        options: &FormatOptions,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let filepath = filepath.as_ref();
+
+        if !filepath.is_file() {
+            return Err(not_found(filepath));
+        }
+
+        let source = read_pyfile(filepath).map_err(|err| BlackError::io(filepath, err))?;
+
+        if is_notebook(filepath) {
+            return self
+                .format_notebook(&source, options, filepath)
+                .map(|notebook| notebook.outcome);
+        }
+
+        let encoding = SourceEncoding::detect(&source);
+        let options = path_options(filepath, options);
+
+        self.format_source(&source, &encoding, &options, filepath)
+            .map(|outcome| relabel_outcome(outcome, filepath))
+    }
+
+    /// Format a Jupyter notebook's code cells one by one, with IPython's magics masked,
+    /// leaving its other cells, outputs and metadata alone. Cells blackd can't parse
+    /// are skipped, and diffs and errors are labelled `filepath:cell_N`.
+    pub fn format_notebook(
+        &self,
+        source: &[u8],
+        options: &FormatOptions,
+        filepath: &Path,
+    ) -> Result<NotebookOutcome, BlackError> {
+        let mut notebook = Notebook::parse(source, filepath)?;
+        let options = cell_options(options)?;
+
+        for cell in notebook.code_cells(filepath) {
+            let result = self.format_source(
+                cell.source.as_bytes(),
+                &str_encoding(&cell.source),
+                &options,
+                &cell.label,
+            );
+
+            notebook.record(&cell, result)?;
+        }
+
+        Ok(notebook.into_outcome())
+    }
+
+    /// Ask blackd which black release it's running (without formatting anything),
+    /// `None` if it's too old to say
+    pub fn probe(&self) -> Result<Option<BlackVersion>, BlackError> {
+        let resp = self
+            .http
+            .post(self.endpoint.as_str())
+            .headers(request_headers(
+                &FormatOptions::new(),
+                &str_encoding(""),
+                &self.headers,
+            ))
+            .body(Vec::new())
+            .send()
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?;
+
+        let status = resp.status();
+        let version = black_version(resp.headers());
+        let body = resp
+            .bytes()
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?
+            .to_vec();
+
+        check_status(status, body, Path::new(&self.url))?;
+
+        Ok(version)
+    }
+
+    /// Send the source to blackd in its own encoding, handing back the reformatted
+    /// code (restored to the source's BOM and line endings) or diff
+    fn format_source(
+        &self,
+        source: &[u8],
+        encoding: &SourceEncoding,
+        options: &FormatOptions,
+        filepath: &Path,
+    ) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+        let resp = self
+            .http
+            .post(self.endpoint.as_str())
+            .headers(request_headers(options, encoding, &self.headers))
+            .body(encoding.strip_bom(source).to_vec())
+            .send()
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?;
+
+        let status = resp.status();
+        let body = resp
+            .bytes()
+            .map_err(|err| BlackError::from(err).at_url(&self.url))?
+            .to_vec();
+
+        outcome_from_response(status, body, source, encoding, options, filepath)
+    }
+}
+
+pub fn is_stub_file(filepath: &Path) -> bool {
+    filepath
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("pyi"))
+        .unwrap_or(false)
+}
+
+/// Swap blackd's generic `In`/`Out` diff labels for the source file's path
+pub fn relabel_diff(diff: &str, filepath: &Path) -> String {
+    let label = filepath.display().to_string();
+
+    diff.split_inclusive('\n')
+        .enumerate()
+        .map(|(idx, line)| match idx {
+            0 if line.starts_with("--- In") => line.replacen("In", label.as_str(), 1),
+            1 if line.starts_with("+++ Out") => line.replacen("Out", label.as_str(), 1),
+            _ => line.to_string(),
+        })
+        .collect()
+}
+
+/// The socket in a `unix:///path/to/blackd.sock` URL
+pub(crate) fn unix_socket_path(url: &str) -> Option<&Path> {
+    url.strip_prefix("unix://").map(Path::new)
+}
+
+/// The URL requests are actually sent to, blackd doesn't care about
+/// the host of a request that came in over a unix socket but reqwest does
+pub(crate) fn request_url(url: &str) -> String {
+    match unix_socket_path(url) {
+        Some(_) if cfg!(unix) => "http://localhost/".to_string(),
+        _ => url.to_string(),
+    }
+}
+
+/// The headers for a request to blackd, shared by the blocking and async clients
+pub(crate) fn request_headers(
+    options: &FormatOptions,
+    encoding: &SourceEncoding,
+    extra: &HeaderMap,
+) -> HeaderMap {
+    let mut headers = headers_from_options(options);
+
+    // Extra headers replace the ones built from the options, but may themselves repeat
+    for name in extra.keys() {
+        headers.remove(name);
+    }
+
+    for (name, value) in extra {
+        headers.append(name, value.clone());
+    }
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&encoding.content_type()).unwrap(),
+    );
+
+    headers
+}
+
+/// The source has already been decoded, whatever its coding cookie says
+pub(crate) fn str_encoding(source: &str) -> SourceEncoding {
+    let mut encoding = SourceEncoding::detect(source.as_bytes());
+    encoding.charset = "utf-8".to_string();
+
+    encoding
+}
+
+/// Stub files are formatted as such regardless of the given options
+pub(crate) fn path_options<'a>(
+    filepath: &Path,
+    options: &'a FormatOptions,
+) -> Cow<'a, FormatOptions> {
+    if is_stub_file(filepath) && !options.pyi {
+        Cow::Owned(options.clone().pyi(true))
+    } else {
+        Cow::Borrowed(options)
+    }
+}
+
+pub(crate) fn not_found(filepath: &Path) -> BlackError {
+    BlackError::io(
+        filepath,
+        io::Error::new(io::ErrorKind::NotFound, "No such file or directory"),
+    )
+}
+
+pub(crate) fn into_string_outcome(outcome: FormatOutcome<Vec<u8>>) -> FormatOutcome<String> {
+    match outcome {
+        FormatOutcome::Changed(formatted) => {
+            FormatOutcome::Changed(String::from_utf8_lossy(&formatted).into_owned())
+        }
+        FormatOutcome::Unchanged => FormatOutcome::Unchanged,
+        FormatOutcome::Diff(diff) => FormatOutcome::Diff(diff),
+    }
+}
+
+pub(crate) fn relabel_outcome<T>(outcome: FormatOutcome<T>, filepath: &Path) -> FormatOutcome<T> {
+    match outcome {
+        FormatOutcome::Diff(diff) => FormatOutcome::Diff(relabel_diff(&diff, filepath)),
+        outcome => outcome,
+    }
+}
+
+/// blackd reports its black release with every response
+pub(crate) fn black_version(headers: &HeaderMap) -> Option<BlackVersion> {
+    headers
+        .get("X-Black-Version")
+        .and_then(|version| version.to_str().ok())
+        .and_then(|version| version.parse().ok())
+}
+
+/// Turn blackd's answer into the reformatted code (restored to the source's
+/// BOM and line endings) or diff
+pub(crate) fn outcome_from_response(
+    status: StatusCode,
+    body: Vec<u8>,
+    source: &[u8],
+    encoding: &SourceEncoding,
+    options: &FormatOptions,
+    filepath: &Path,
+) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+    let formatted = match check_status(status, body, filepath)? {
+        Some(formatted) => formatted,
+        None => return Ok(FormatOutcome::Unchanged),
+    };
+
+    if options.diff {
+        return Ok(FormatOutcome::Diff(encoding.decode(&formatted)));
+    }
+
+    let encoding_error = |message| BlackError::Encoding {
+        path: filepath.to_path_buf(),
+        message,
+    };
+
+    let restored = if options.skip_source_first_line {
+        // The skipped line is put back exactly as it was (BOM, line ending, invalid bytes and all)
+        let (first_line, _) = split_first_line(source);
+        let (_, formatted) = split_first_line(encoding.strip_bom(&formatted));
+
+        let rest = SourceEncoding {
+            bom: false,
+            ..encoding.clone()
+        }
+        .restore(formatted.to_vec())
+        .map_err(encoding_error)?;
+
+        [first_line, rest.as_slice()].concat()
+    } else {
+        encoding.restore(formatted).map_err(encoding_error)?
+    };
+
+    // Older blackd releases "reformat" CRLF files to LF, which restoring undoes
+    if restored == source {
+        Ok(FormatOutcome::Unchanged)
+    } else {
+        Ok(FormatOutcome::Changed(restored))
+    }
+}
+
+/// The first line (including its line ending) and everything after it
+fn split_first_line(data: &[u8]) -> (&[u8], &[u8]) {
+    match data.iter().position(|byte| *byte == b'\n') {
+        Some(idx) => data.split_at(idx + 1),
+        None => (data, &[]),
+    }
+}
+
+pub(crate) fn check_status(
+    status: StatusCode,
+    body: Vec<u8>,
+    filepath: &Path,
+) -> Result<Option<Vec<u8>>, BlackError> {
+    if status == StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    if status == StatusCode::OK {
+        return Ok(Some(body));
+    }
+    let (path, message) = (
+        filepath.to_path_buf(),
+        String::from_utf8_lossy(&body).into_owned(),
+    );
+
+    Err(match status {
+        // blackd reports headers it can't make sense of as "Invalid value for X-...: ..."
+        StatusCode::BAD_REQUEST if message.starts_with("Invalid value for") => {
+            BlackError::RejectedOption { message }
+        }
+        StatusCode::BAD_REQUEST => BlackError::Syntax { path, message },
+        StatusCode::INTERNAL_SERVER_ERROR => BlackError::Internal {
+            path,
+            status,
+            message,
+        },
+        StatusCode::NOT_IMPLEMENTED => BlackError::UnsupportedProtocol {
+            path,
+            status,
+            message,
+        },
+        _ => BlackError::UnexpectedStatus {
+            path,
+            status,
+            message,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &[u8], formatted: &[u8], options: &FormatOptions) -> FormatOutcome<Vec<u8>> {
+        outcome_from_response(
+            StatusCode::OK,
+            formatted.to_vec(),
+            source,
+            &SourceEncoding::detect(source),
+            options,
+            Path::new("test.py"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn restores_bom_and_crlf() {
+        let source = b"\xef\xbb\xbfx = 'a'\r\n";
+
+        assert_eq!(
+            format(source, b"x = \"a\"\n", &FormatOptions::new()),
+            FormatOutcome::Changed(b"\xef\xbb\xbfx = \"a\"\r\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn lf_output_for_a_crlf_source_is_unchanged() {
+        assert_eq!(
+            format(b"x = 1\r\n", b"x = 1\n", &FormatOptions::new()),
+            FormatOutcome::Unchanged
+        );
+    }
+
+    #[test]
+    fn no_content_is_unchanged() {
+        let outcome = outcome_from_response(
+            StatusCode::NO_CONTENT,
+            Vec::new(),
+            b"x = 1\n",
+            &SourceEncoding::detect(b"x = 1\n"),
+            &FormatOptions::new(),
+            Path::new("test.py"),
+        );
+
+        assert_eq!(outcome.unwrap(), FormatOutcome::Unchanged);
+    }
+
+    #[test]
+    fn keeps_the_skipped_first_line_verbatim() {
+        let options = FormatOptions::new().skip_source_first_line(true);
+        // The first line has a BOM, a CRLF ending and bytes that aren't valid utf-8
+        let source = b"\xef\xbb\xbfgarbage \xff line\r\nx = 'a'\r\n";
+
+        assert_eq!(
+            format(source, b"mangled first line\nx = \"a\"\n", &options),
+            FormatOutcome::Changed(b"\xef\xbb\xbfgarbage \xff line\r\nx = \"a\"\r\n".to_vec())
+        );
+    }
+
+    #[test]
+    fn a_skipped_first_line_alone_is_unchanged() {
+        let options = FormatOptions::new().skip_source_first_line(true);
+
+        assert_eq!(
+            format(b"#!python\r\n", b"#!python\n", &options),
+            FormatOutcome::Unchanged
+        );
+    }
+}
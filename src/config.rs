@@ -0,0 +1,276 @@
+use crate::origins::{Origin, Origins};
+use crate::sources::parse_regex;
+use crate::{parse_unstable_feature, CliOptions};
+use blackd_client::parse_py_versions;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Find the `pyproject.toml` governing the supplied project root, if there is one
+pub fn find_pyproject_toml(root: &Path) -> Option<PathBuf> {
+    let candidate = root.join("pyproject.toml");
+
+    if candidate.is_file() {
+        Some(candidate)
+    } else {
+        None
+    }
+}
+
+/// Read the `[tool.black]` table out of the specified `pyproject.toml`
+pub fn read_pyproject_toml(path: &Path) -> Result<Table, String> {
+    let contents =
+        fs::read_to_string(path).map_err(|err| format!("Could not read {:?}: {}", path, err))?;
+
+    let document: Table = toml::from_str(contents.as_str())
+        .map_err(|err| format!("Could not parse {:?}: {}", path, err))?;
+
+    let config = match document.get("tool").and_then(|tool| tool.get("black")) {
+        Some(Value::Table(config)) => config.clone(),
+        Some(_) => return Err(format!("[tool.black] in {:?} is not a table", path)),
+        None => Table::new(),
+    };
+
+    // black accepts both `line-length` and `line_length` (and even `--line-length`)
+    Ok(config
+        .into_iter()
+        .map(|(key, value)| (key.trim_start_matches("--").replace('-', "_"), value))
+        .collect())
+}
+
+/// Fill in every option that wasn't given on the command line or in the environment from the config
+pub fn apply_config(
+    options: &mut CliOptions,
+    origins: &mut Origins,
+    config: &Table,
+    path: &Path,
+) -> Result<(), String> {
+    for (key, value) in config.iter() {
+        let key = key.as_str();
+
+        let applied = match key {
+            "line_length" if !origins.is_set(key) => {
+                options.line_length = Some(config_int(key, value)?);
+                true
+            }
+            "target_version" if !origins.is_set(key) => {
+                options.target_version = Some(parse_py_versions(&config_str_list(key, value)?)?);
+                true
+            }
+            "pyi" if !origins.is_set(key) => {
+                options.pyi = config_bool(key, value)?;
+                true
+            }
+            "skip_string_normalization" if !origins.is_set(key) => {
+                options.skip_string_normalization = config_bool(key, value)?;
+                true
+            }
+            "skip_magic_trailing_comma" if !origins.is_set(key) => {
+                options.skip_magic_trailing_comma = config_bool(key, value)?;
+                true
+            }
+            "skip_source_first_line" if !origins.is_set(key) => {
+                options.skip_source_first_line = config_bool(key, value)?;
+                true
+            }
+            "preview" if !origins.is_set(key) => {
+                options.preview = config_bool(key, value)?;
+                true
+            }
+            "unstable" if !origins.is_set(key) => {
+                options.unstable = config_bool(key, value)?;
+                true
+            }
+            "enable_unstable_feature" if !origins.is_set(key) => {
+                options.enable_unstable_feature = config_str_list(key, value)?
+                    .split(',')
+                    .filter(|feature| !feature.trim().is_empty())
+                    .map(parse_unstable_feature)
+                    .collect::<Result<Vec<String>, String>>()?;
+                true
+            }
+            // An explicit --fast or --safe wins
+            "fast" if !origins.is_set("fast") && !origins.is_set("safe") => {
+                options.fast = config_bool(key, value)?;
+                options.safe = !options.fast;
+                origins.set("safe", Origin::Config(path.to_path_buf()));
+                true
+            }
+            "safe" if !origins.is_set("fast") && !origins.is_set("safe") => {
+                options.safe = config_bool(key, value)?;
+                options.fast = !options.safe;
+                origins.set("fast", Origin::Config(path.to_path_buf()));
+                true
+            }
+            "diff" if !origins.is_set(key) => {
+                options.diff = config_bool(key, value)?;
+                true
+            }
+            "color" if !origins.is_set(key) => {
+                options.color = config_bool(key, value)?;
+                true
+            }
+            "workers" if !origins.is_set(key) => {
+                options.workers = Some(config_int(key, value)?);
+                true
+            }
+            "include" if !origins.is_set(key) => {
+                options.include = Some(parse_regex(config_str(key, value)?)?);
+                true
+            }
+            "exclude" if !origins.is_set(key) => {
+                options.exclude = Some(parse_regex(config_str(key, value)?)?);
+                true
+            }
+            "extend_exclude" if !origins.is_set(key) => {
+                options.extend_exclude = Some(parse_regex(config_str(key, value)?)?);
+                true
+            }
+            "force_exclude" if !origins.is_set(key) => {
+                options.force_exclude = Some(parse_regex(config_str(key, value)?)?);
+                true
+            }
+            // Anything else is either meaningless to blackd or handled by black itself
+            _ => false,
+        };
+
+        if applied {
+            origins.set(key, Origin::Config(path.to_path_buf()));
+        }
+    }
+
+    Ok(())
+}
+
+fn config_bool(key: &str, value: &Value) -> Result<bool, String> {
+    value.as_bool().ok_or_else(|| {
+        format!(
+            "Invalid value for {} in [tool.black]: expected a boolean",
+            key
+        )
+    })
+}
+
+fn config_int<T: TryFrom<i64>>(key: &str, value: &Value) -> Result<T, String> {
+    value
+        .as_integer()
+        .and_then(|val| T::try_from(val).ok())
+        .ok_or_else(|| {
+            format!(
+                "Invalid value for {} in [tool.black]: expected a positive integer",
+                key
+            )
+        })
+}
+
+fn config_str<'a>(key: &str, value: &'a Value) -> Result<&'a str, String> {
+    value.as_str().ok_or_else(|| {
+        format!(
+            "Invalid value for {} in [tool.black]: expected a string",
+            key
+        )
+    })
+}
+
+fn config_str_list(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(val) => Ok(val.clone()),
+        Value::Array(vals) => vals
+            .iter()
+            .map(|val| config_str(key, val).map(String::from))
+            .collect::<Result<Vec<String>, String>>()
+            .map(|vals| vals.join(",")),
+        _ => Err(format!(
+            "Invalid value for {} in [tool.black]: expected a list of strings",
+            key
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argh::FromArgs;
+
+    fn configured(args: &[&str], config: &str) -> (CliOptions, Origins) {
+        let mut options = CliOptions::from_args(&["blackd-client"], args).unwrap();
+        let mut origins = Origins::from_cli(&options);
+        let config: Table = toml::from_str(config).unwrap();
+
+        apply_config(
+            &mut options,
+            &mut origins,
+            &config,
+            Path::new("pyproject.toml"),
+        )
+        .unwrap();
+
+        (options, origins)
+    }
+
+    #[test]
+    fn reads_tool_black_with_any_key_spelling() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("pyproject.toml");
+        fs::write(
+            &path,
+            "[tool.isort]\nprofile = 'black'\n\n[tool.black]\nline-length = 100\n\"--skip-string-normalization\" = true\n",
+        )
+        .unwrap();
+
+        let config = read_pyproject_toml(&path).unwrap();
+
+        assert_eq!(config.get("line_length"), Some(&Value::Integer(100)));
+        assert_eq!(
+            config.get("skip_string_normalization"),
+            Some(&Value::Boolean(true))
+        );
+        assert_eq!(config.len(), 2);
+    }
+
+    #[test]
+    fn the_command_line_beats_the_config() {
+        let (options, origins) = configured(
+            &["--line-length", "100", "src"],
+            "line_length = 80\npreview = true\n",
+        );
+
+        assert_eq!(options.line_length, Some(100));
+        assert_eq!(*origins.get("line_length"), Origin::Cli);
+        assert!(options.preview);
+        assert_eq!(
+            *origins.get("preview"),
+            Origin::Config(PathBuf::from("pyproject.toml"))
+        );
+    }
+
+    #[test]
+    fn fast_and_safe_are_set_together() {
+        let (options, origins) = configured(&["src"], "fast = true\n");
+
+        assert!(options.fast && !options.safe);
+        assert!(matches!(origins.get("safe"), Origin::Config(_)));
+
+        // --safe on the command line wins over the config's fast
+        let (options, origins) = configured(&["--safe", "src"], "fast = true\n");
+
+        assert!(!options.fast && options.safe);
+        assert_eq!(*origins.get("fast"), Origin::Default);
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let mut options = CliOptions::default();
+        let mut origins = Origins::from_cli(&options);
+        let config: Table = toml::from_str("line_length = 'long'\n").unwrap();
+
+        assert!(apply_config(
+            &mut options,
+            &mut origins,
+            &config,
+            Path::new("pyproject.toml")
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use blackd_client::BlackError;
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a freshly spawned blackd gets to start accepting connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to check whether a freshly spawned blackd is accepting connections yet
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A blackd process started by the client, which is shut down again when
+/// dropped unless it's meant to be left running for subsequent invocations
+#[derive(Debug)]
+pub struct SpawnedBlackd {
+    child: Child,
+    keep_running: bool,
+}
+
+impl Drop for SpawnedBlackd {
+    fn drop(&mut self) {
+        if !self.keep_running {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Make sure something is listening on `host:port`, launching
+/// `blackd_path` to listen there if nothing currently is
+pub fn ensure_blackd(
+    host: &str,
+    port: u16,
+    blackd_path: &str,
+    keep_running: bool,
+) -> Result<Option<SpawnedBlackd>, BlackError> {
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| BlackError::Spawn {
+            message: format!("Could not resolve {}:{}: {}", host, port, err),
+        })?
+        .collect();
+
+    match connect(&addrs) {
+        Ok(_) => return Ok(None),
+        Err(err) if err.kind() != io::ErrorKind::ConnectionRefused => {
+            return Err(BlackError::Spawn {
+                message: format!("Could not check for blackd on {}:{}: {}", host, port, err),
+            })
+        }
+        Err(_) => {}
+    }
+
+    let mut command = Command::new(blackd_path);
+
+    command
+        .args([
+            "--bind-host",
+            host,
+            "--bind-port",
+            port.to_string().as_str(),
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // A daemonized blackd shouldn't go down with the terminal's process group on Ctrl-C
+    #[cfg(unix)]
+    if keep_running {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let child = command.spawn().map_err(|err| BlackError::Spawn {
+        message: format!("Could not launch {:?}: {}", blackd_path, err),
+    })?;
+
+    let mut spawned = SpawnedBlackd {
+        child,
+        keep_running,
+    };
+
+    let started = Instant::now();
+
+    while started.elapsed() < STARTUP_TIMEOUT {
+        if connect(&addrs).is_ok() {
+            return Ok(Some(spawned));
+        }
+
+        let exited = spawned.child.try_wait().map_err(|err| BlackError::Spawn {
+            message: format!("Could not check on {:?}: {}", blackd_path, err),
+        })?;
+
+        if let Some(status) = exited {
+            return Err(BlackError::Spawn {
+                message: format!(
+                    "{:?} exited before accepting connections ({})",
+                    blackd_path, status
+                ),
+            });
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    // Don't leave a half-started blackd lying around, even if it was meant to be kept
+    spawned.keep_running = false;
+
+    Err(BlackError::Spawn {
+        message: format!(
+            "{:?} did not start accepting connections on {}:{} within {} seconds",
+            blackd_path,
+            host,
+            port,
+            STARTUP_TIMEOUT.as_secs()
+        ),
+    })
+}
+
+fn connect(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_err = io::Error::new(io::ErrorKind::AddrNotAvailable, "could not resolve address");
+
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, POLL_INTERVAL * 10) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+
+    Err(last_err)
+}
@@ -0,0 +1,218 @@
+use regex::bytes::Regex;
+
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// The line terminator used by a source file, judged by its first line (as black does)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Newline {
+    Lf,
+    CrLf,
+}
+
+/// How a python source file is encoded on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceEncoding {
+    pub charset: String,
+    pub bom: bool,
+    pub newline: Newline,
+}
+
+impl SourceEncoding {
+    /// Detect a source's encoding from its BOM or PEP 263 coding cookie, defaulting to utf-8
+    pub fn detect(source: &[u8]) -> SourceEncoding {
+        let bom = source.starts_with(UTF8_BOM);
+        let source = if bom {
+            &source[UTF8_BOM.len()..]
+        } else {
+            source
+        };
+
+        let charset = if bom {
+            "utf-8".to_string()
+        } else {
+            coding_cookie(source).unwrap_or_else(|| "utf-8".to_string())
+        };
+
+        let newline = match source.iter().position(|byte| *byte == b'\n') {
+            Some(idx) if idx > 0 && source[idx - 1] == b'\r' => Newline::CrLf,
+            _ => Newline::Lf,
+        };
+
+        SourceEncoding {
+            charset,
+            bom,
+            newline,
+        }
+    }
+
+    pub fn is_utf8(&self) -> bool {
+        matches!(self.charset.as_str(), "utf-8" | "utf8" | "utf_8")
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("text/plain; charset={}", self.charset)
+    }
+
+    /// The source as it should be sent to blackd, without any BOM (which blackd would choke on)
+    pub fn strip_bom<'a>(&self, source: &'a [u8]) -> &'a [u8] {
+        if self.bom && source.starts_with(UTF8_BOM) {
+            &source[UTF8_BOM.len()..]
+        } else {
+            source
+        }
+    }
+
+    /// Put blackd's output back into the original's BOM and line endings,
+    /// making sure it's still valid in the original's encoding
+    pub fn restore(&self, formatted: Vec<u8>) -> Result<Vec<u8>, String> {
+        let formatted = self.strip_bom(&formatted);
+
+        if self.is_utf8() && std::str::from_utf8(formatted).is_err() {
+            return Err("blackd returned code that isn't valid utf-8".to_string());
+        }
+
+        let mut restored: Vec<u8> = Vec::with_capacity(formatted.len() + UTF8_BOM.len());
+
+        if self.bom {
+            restored.extend_from_slice(UTF8_BOM);
+        }
+
+        match self.newline {
+            Newline::Lf => restored.extend_from_slice(formatted),
+            // Older blackd releases don't preserve CRLF endings themselves
+            Newline::CrLf => {
+                for (idx, byte) in formatted.iter().enumerate() {
+                    if *byte == b'\n' && (idx == 0 || formatted[idx - 1] != b'\r') {
+                        restored.push(b'\r');
+                    }
+                    restored.push(*byte);
+                }
+            }
+        }
+
+        Ok(restored)
+    }
+
+    /// Decode text (i.e. a diff) returned by blackd for display
+    pub fn decode(&self, data: &[u8]) -> String {
+        match self.charset.as_str() {
+            "latin-1" | "latin1" | "latin_1" | "iso-8859-1" | "iso8859-1" | "l1" => {
+                data.iter().map(|byte| char::from(*byte)).collect()
+            }
+            _ => String::from_utf8_lossy(self.strip_bom(data)).into_owned(),
+        }
+    }
+}
+
+/// Find a PEP 263 coding cookie, which is only honored on the first line
+/// or on the second line when the first is itself a comment or blank
+fn coding_cookie(source: &[u8]) -> Option<String> {
+    let cookie = Regex::new(r"^[ \t\f]*#.*?coding[:=][ \t]*([-\w.]+)").unwrap();
+    let blank_or_comment = Regex::new(r"^[ \t\f]*(?:[#\r\n]|$)").unwrap();
+
+    for (idx, line) in source.split(|byte| *byte == b'\n').take(2).enumerate() {
+        if let Some(found) = cookie.captures(line) {
+            let charset = String::from_utf8_lossy(&found[1]).to_ascii_lowercase();
+
+            // Python treats every spelling of utf-8 (including utf-8-sig) the same
+            return Some(match charset.as_str() {
+                "utf8" | "utf_8" | "utf-8-sig" | "utf_8_sig" => "utf-8".to_string(),
+                _ => charset,
+            });
+        }
+
+        if idx == 0 && !blank_or_comment.is_match(line) {
+            break;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_and_restores_a_bom() {
+        let source = b"\xef\xbb\xbfx = 1\n";
+        let encoding = SourceEncoding::detect(source);
+
+        assert!(encoding.bom);
+        assert_eq!(encoding.charset, "utf-8");
+        assert_eq!(encoding.strip_bom(source), b"x = 1\n");
+        assert_eq!(
+            encoding.restore(b"x = 2\n".to_vec()).unwrap(),
+            b"\xef\xbb\xbfx = 2\n"
+        );
+        // A BOM blackd kept isn't doubled up
+        assert_eq!(
+            encoding.restore(b"\xef\xbb\xbfx = 2\n".to_vec()).unwrap(),
+            b"\xef\xbb\xbfx = 2\n"
+        );
+    }
+
+    #[test]
+    fn reapplies_crlf_line_endings() {
+        let encoding = SourceEncoding::detect(b"x = 1\r\ny = 2\r\n");
+
+        assert_eq!(encoding.newline, Newline::CrLf);
+        assert_eq!(
+            encoding.restore(b"x = 1\ny = 2\r\n".to_vec()).unwrap(),
+            b"x = 1\r\ny = 2\r\n"
+        );
+    }
+
+    #[test]
+    fn judges_line_endings_by_the_first_line() {
+        assert_eq!(
+            SourceEncoding::detect(b"x = 1\ny = 2\r\n").newline,
+            Newline::Lf
+        );
+        assert_eq!(SourceEncoding::detect(b"x = 1").newline, Newline::Lf);
+    }
+
+    #[test]
+    fn reads_a_coding_cookie_from_the_first_two_lines() {
+        let charset = |source: &[u8]| SourceEncoding::detect(source).charset;
+
+        assert_eq!(charset(b"# -*- coding: latin-1 -*-\nx = 1\n"), "latin-1");
+        assert_eq!(
+            charset(b"#!/usr/bin/env python\n# coding=latin-1\n"),
+            "latin-1"
+        );
+        assert_eq!(charset(b"\n# vim: set fileencoding=cp1252 :\n"), "cp1252");
+        assert_eq!(charset(b"# coding: UTF8\n"), "utf-8");
+    }
+
+    #[test]
+    fn ignores_a_cookie_after_code_or_past_the_second_line() {
+        let charset = |source: &[u8]| SourceEncoding::detect(source).charset;
+
+        assert_eq!(charset(b"x = 1\n# coding: latin-1\n"), "utf-8");
+        assert_eq!(
+            charset(b"#!/usr/bin/env python\n\n# coding: latin-1\n"),
+            "utf-8"
+        );
+    }
+
+    #[test]
+    fn a_bom_wins_over_the_cookie() {
+        let encoding = SourceEncoding::detect(b"\xef\xbb\xbf# coding: latin-1\n");
+
+        assert_eq!(encoding.charset, "utf-8");
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_from_blackd() {
+        let encoding = SourceEncoding::detect(b"x = 1\n");
+
+        assert!(encoding.restore(b"x = '\xff'\n".to_vec()).is_err());
+        // Other encodings are passed through as they are
+        let latin1 = SourceEncoding::detect(b"# coding: latin-1\n");
+        assert_eq!(
+            latin1.restore(b"x = '\xe9'\n".to_vec()).unwrap(),
+            b"x = '\xe9'\n"
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use crate::origins::{Origin, Origins};
+use crate::sources::parse_regex;
+use crate::{parse_seconds, parse_unstable_feature, CliOptions};
+use blackd_client::{parse_py_versions, LineRange};
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The variable an option can be set from, e.g. `BLACKD_LINE_LENGTH` for `--line-length`
+fn env_var(name: &str) -> String {
+    format!("BLACKD_{}", name.to_ascii_uppercase())
+}
+
+/// Fill in every option that wasn't given on the command line from its `BLACKD_*` variable
+pub fn apply_env(options: &mut CliOptions, origins: &mut Origins) -> Result<(), String> {
+    for name in origins.names() {
+        let var = env_var(name);
+
+        let value = match env::var(&var) {
+            Ok(value) => value,
+            Err(env::VarError::NotPresent) => continue,
+            Err(env::VarError::NotUnicode(_)) => {
+                return Err(format!("Invalid value for {}: not valid unicode", var))
+            }
+        };
+
+        let applied = match name {
+            "host" if !origins.is_set(name) => {
+                options.host = Some(value);
+                true
+            }
+            "port" if !origins.is_set(name) => {
+                options.port = Some(env_parse(&var, &value)?);
+                true
+            }
+            "socket" if !origins.is_set(name) => {
+                options.socket = Some(value);
+                true
+            }
+            "url" if !origins.is_set(name) => {
+                options.url = Some(value);
+                true
+            }
+            "ca_cert" if !origins.is_set(name) => {
+                options.ca_cert = Some(value);
+                true
+            }
+            "client_cert" if !origins.is_set(name) => {
+                options.client_cert = Some(value);
+                true
+            }
+            "client_key" if !origins.is_set(name) => {
+                options.client_key = Some(value);
+                true
+            }
+            // Header values may well contain commas, so multiple headers go on separate lines
+            "header" if !origins.is_set(name) => {
+                options.header = value
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(String::from)
+                    .collect();
+                true
+            }
+            "spawn_blackd" if !origins.is_set(name) => {
+                options.spawn_blackd = env_bool(&var, &value)?;
+                true
+            }
+            "blackd_path" if !origins.is_set(name) => {
+                options.blackd_path = Some(value);
+                true
+            }
+            "keep_blackd" if !origins.is_set(name) => {
+                options.keep_blackd = env_bool(&var, &value)?;
+                true
+            }
+            "version_check" if !origins.is_set(name) => {
+                options.version_check = env_bool(&var, &value)?;
+                true
+            }
+            "verbose" if !origins.is_set(name) => {
+                options.verbose = env_bool(&var, &value)?;
+                true
+            }
+            "config" if !origins.is_set(name) => {
+                options.config = Some(value);
+                true
+            }
+            "show_config" if !origins.is_set(name) => {
+                options.show_config = env_bool(&var, &value)?;
+                true
+            }
+            "line_length" if !origins.is_set(name) => {
+                options.line_length = Some(env_parse(&var, &value)?);
+                true
+            }
+            "target_version" if !origins.is_set(name) => {
+                options.target_version = Some(
+                    parse_py_versions(&value)
+                        .map_err(|err| format!("Invalid value for {}: {}", var, err))?,
+                );
+                true
+            }
+            "pyi" if !origins.is_set(name) => {
+                options.pyi = env_bool(&var, &value)?;
+                true
+            }
+            "skip_string_normalization" if !origins.is_set(name) => {
+                options.skip_string_normalization = env_bool(&var, &value)?;
+                true
+            }
+            "skip_magic_trailing_comma" if !origins.is_set(name) => {
+                options.skip_magic_trailing_comma = env_bool(&var, &value)?;
+                true
+            }
+            "skip_source_first_line" if !origins.is_set(name) => {
+                options.skip_source_first_line = env_bool(&var, &value)?;
+                true
+            }
+            "preview" if !origins.is_set(name) => {
+                options.preview = env_bool(&var, &value)?;
+                true
+            }
+            "unstable" if !origins.is_set(name) => {
+                options.unstable = env_bool(&var, &value)?;
+                true
+            }
+            "enable_unstable_feature" if !origins.is_set(name) => {
+                options.enable_unstable_feature = value
+                    .split(',')
+                    .filter(|feature| !feature.trim().is_empty())
+                    .map(|feature| {
+                        parse_unstable_feature(feature)
+                            .map_err(|err| format!("Invalid value for {}: {}", var, err))
+                    })
+                    .collect::<Result<Vec<String>, String>>()?;
+                true
+            }
+            "line_ranges" if !origins.is_set(name) => {
+                options.line_ranges = value
+                    .split(',')
+                    .filter(|range| !range.trim().is_empty())
+                    .map(|range| {
+                        range
+                            .parse::<LineRange>()
+                            .map_err(|err| format!("Invalid value for {}: {}", var, err))
+                    })
+                    .collect::<Result<Vec<LineRange>, String>>()?;
+                true
+            }
+            "changed_since" if !origins.is_set(name) => {
+                options.changed_since = Some(value);
+                true
+            }
+            // An explicit --fast or --safe on the command line wins
+            "fast" if !origins.is_set("fast") && !origins.is_set("safe") => {
+                options.fast = env_bool(&var, &value)?;
+                options.safe = !options.fast;
+                origins.set("safe", Origin::Env(var.clone()));
+                true
+            }
+            "safe" if !origins.is_set("fast") && !origins.is_set("safe") => {
+                options.safe = env_bool(&var, &value)?;
+                options.fast = !options.safe;
+                origins.set("fast", Origin::Env(var.clone()));
+                true
+            }
+            "check" if !origins.is_set(name) => {
+                options.check = env_bool(&var, &value)?;
+                true
+            }
+            "diff" if !origins.is_set(name) => {
+                options.diff = env_bool(&var, &value)?;
+                true
+            }
+            "color" if !origins.is_set(name) => {
+                options.color = env_bool(&var, &value)?;
+                true
+            }
+            "include" if !origins.is_set(name) => {
+                options.include = Some(env_regex(&var, &value)?);
+                true
+            }
+            "exclude" if !origins.is_set(name) => {
+                options.exclude = Some(env_regex(&var, &value)?);
+                true
+            }
+            "extend_exclude" if !origins.is_set(name) => {
+                options.extend_exclude = Some(env_regex(&var, &value)?);
+                true
+            }
+            "force_exclude" if !origins.is_set(name) => {
+                options.force_exclude = Some(env_regex(&var, &value)?);
+                true
+            }
+            "timeout" if !origins.is_set(name) => {
+                options.timeout = Some(env_seconds(&var, &value)?);
+                true
+            }
+            "connect_timeout" if !origins.is_set(name) => {
+                options.connect_timeout = Some(env_seconds(&var, &value)?);
+                true
+            }
+            "retries" if !origins.is_set(name) => {
+                options.retries = Some(env_parse(&var, &value)?);
+                true
+            }
+            "workers" if !origins.is_set(name) => {
+                options.workers = Some(env_parse(&var, &value)?);
+                true
+            }
+            "stdin_filename" if !origins.is_set(name) => {
+                options.stdin_filename = Some(value);
+                true
+            }
+            // Sources are separated like PATH entries
+            "src" if !origins.is_set(name) => {
+                options.src = env::split_paths(&value)
+                    .filter(|path| !path.as_os_str().is_empty())
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .collect();
+                true
+            }
+            _ => false,
+        };
+
+        if applied {
+            origins.set(name, Origin::Env(var));
+        }
+    }
+
+    Ok(())
+}
+
+fn env_bool(var: &str, value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!(
+            "Invalid value for {}: expected a boolean (1/0, true/false, yes/no or on/off)",
+            var
+        )),
+    }
+}
+
+fn env_parse<T: FromStr>(var: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("Invalid value for {}: expected a positive integer", var))
+}
+
+fn env_seconds(var: &str, value: &str) -> Result<Duration, String> {
+    parse_seconds(value).map_err(|err| format!("Invalid value for {}: {}", var, err))
+}
+
+fn env_regex(var: &str, value: &str) -> Result<regex::Regex, String> {
+    parse_regex(value).map_err(|err| format!("Invalid value for {}: {}", var, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::apply_config;
+    use argh::FromArgs;
+    use std::path::{Path, PathBuf};
+
+    fn resolve(args: &[&str], config: &str) -> (CliOptions, Origins) {
+        let mut options = CliOptions::from_args(&["blackd-client"], args).unwrap();
+        let mut origins = Origins::from_cli(&options);
+        let config: toml::Table = toml::from_str(config).unwrap();
+
+        apply_env(&mut options, &mut origins).unwrap();
+        apply_config(
+            &mut options,
+            &mut origins,
+            &config,
+            Path::new("pyproject.toml"),
+        )
+        .unwrap();
+
+        (options, origins)
+    }
+
+    // The environment is shared by every test thread, so this is the only test that touches it
+    #[test]
+    fn the_environment_sits_between_the_command_line_and_the_config() {
+        let env = Origin::Env;
+        let config = Origin::Config(PathBuf::from("pyproject.toml"));
+
+        env::set_var("BLACKD_LINE_LENGTH", "90");
+        env::set_var("BLACKD_PREVIEW", "0");
+        env::set_var("BLACKD_SKIP_STRING_NORMALIZATION", "yes");
+
+        let (options, origins) = resolve(
+            &["--line-length", "100", "src"],
+            "line_length = 80\npreview = true\nskip_magic_trailing_comma = true\nfast = true\n",
+        );
+
+        assert_eq!(options.line_length, Some(100));
+        assert_eq!(*origins.get("line_length"), Origin::Cli);
+        assert!(!options.preview);
+        assert_eq!(*origins.get("preview"), env("BLACKD_PREVIEW".to_string()));
+        assert!(options.skip_string_normalization);
+        assert!(options.skip_magic_trailing_comma);
+        assert_eq!(*origins.get("skip_magic_trailing_comma"), config);
+        assert!(options.fast && !options.safe);
+        assert_eq!(*origins.get("safe"), config);
+
+        env::remove_var("BLACKD_LINE_LENGTH");
+        env::remove_var("BLACKD_PREVIEW");
+        env::remove_var("BLACKD_SKIP_STRING_NORMALIZATION");
+
+        // Either half of the fast/safe pair in the environment beats both halves in the config
+        env::set_var("BLACKD_SAFE", "0");
+
+        let (options, origins) = resolve(&["src"], "fast = false\n");
+
+        assert!(options.fast && !options.safe);
+        assert_eq!(*origins.get("fast"), env("BLACKD_SAFE".to_string()));
+
+        // ... but not an explicit --safe
+        let (options, origins) = resolve(&["--safe", "src"], "");
+
+        assert!(!options.fast && options.safe);
+        assert_eq!(*origins.get("safe"), Origin::Cli);
+
+        env::set_var("BLACKD_SAFE", "maybe");
+
+        let mut options = CliOptions::default();
+        let err = apply_env(&mut options, &mut Origins::from_cli(&CliOptions::default()));
+
+        env::remove_var("BLACKD_SAFE");
+
+        assert!(err.unwrap_err().contains("BLACKD_SAFE"));
+    }
+}
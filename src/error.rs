@@ -0,0 +1,238 @@
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Everything that can go wrong while formatting code with blackd
+#[derive(Debug)]
+pub enum BlackError {
+    /// the configuration (pyproject.toml or command line) is invalid
+    Config { message: String },
+    /// a local blackd couldn't be launched
+    Spawn { message: String },
+    /// nothing is listening at blackd's address
+    Connection { url: String, source: reqwest::Error },
+    /// the request to blackd failed after a connection was made
+    Request { source: reqwest::Error },
+    /// blackd couldn't parse the source (HTTP 400)
+    Syntax { path: PathBuf, message: String },
+    /// blackd doesn't accept one of the requested options, e.g. an unknown unstable feature (HTTP 400)
+    RejectedOption { message: String },
+    /// blackd crashed while formatting the source (HTTP 500)
+    Internal {
+        path: PathBuf,
+        status: StatusCode,
+        message: String,
+    },
+    /// blackd doesn't speak the protocol version the client does (HTTP 501)
+    UnsupportedProtocol {
+        path: PathBuf,
+        status: StatusCode,
+        message: String,
+    },
+    /// blackd answered with a status code the protocol doesn't define
+    UnexpectedStatus {
+        path: PathBuf,
+        status: StatusCode,
+        message: String,
+    },
+    /// blackd's output isn't valid in the source's encoding
+    Encoding { path: PathBuf, message: String },
+    /// a source file couldn't be read or written
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl BlackError {
+    pub fn io<P: AsRef<Path>>(path: P, source: io::Error) -> BlackError {
+        BlackError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// reqwest only knows the URL a request was sent to, not the (e.g. unix socket) address it was meant for
+    pub(crate) fn at_url(self, url: &str) -> BlackError {
+        match self {
+            BlackError::Connection { source, .. } => BlackError::Connection {
+                url: url.to_string(),
+                source,
+            },
+            err => err,
+        }
+    }
+
+    /// Whether the same request might well succeed if it's retried, e.g. because blackd is
+    /// (re)starting, as opposed to failing again the same way (e.g. a syntax error)
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BlackError::Connection { .. } => true,
+            BlackError::Request { source } => !source.is_timeout() && !source.is_builder(),
+            BlackError::Internal { .. } => true,
+            BlackError::UnexpectedStatus { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+
+    /// The process exit code for this error: black's own 123 for sources blackd
+    /// couldn't format (whatever the reason), and the closest `sysexits.h` code
+    /// for setup and transport failures
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BlackError::Config { .. } => 78,
+            BlackError::Spawn { .. } => 71,
+            BlackError::Connection { .. } => 69,
+            BlackError::Request { .. } => 75,
+            BlackError::Syntax { .. }
+            | BlackError::Internal { .. }
+            | BlackError::UnexpectedStatus { .. }
+            | BlackError::Encoding { .. } => 123,
+            BlackError::RejectedOption { .. } => 64,
+            BlackError::UnsupportedProtocol { .. } => 76,
+            BlackError::Io { .. } => 74,
+        }
+    }
+}
+
+impl fmt::Display for BlackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlackError::Config { message } => write!(f, "{}", message),
+            BlackError::Spawn { message } => write!(f, "{}", message),
+            BlackError::Connection { url, source } => write!(
+                f,
+                "could not connect to blackd at {}: {}",
+                url,
+                root_cause(source)
+            ),
+            BlackError::Request { source } => {
+                write!(f, "request to blackd failed: {}", root_cause(source))
+            }
+            BlackError::Syntax { path, message } => {
+                write!(f, "cannot format {}: {}", path.display(), message.trim())
+            }
+            BlackError::RejectedOption { message } => {
+                write!(
+                    f,
+                    "blackd rejected the requested options: {}",
+                    message.trim()
+                )
+            }
+            BlackError::Internal {
+                path,
+                status,
+                message,
+            } => write!(
+                f,
+                "cannot format {}: blackd encountered an internal error ({}){}",
+                path.display(),
+                status,
+                details(message)
+            ),
+            BlackError::UnsupportedProtocol {
+                path,
+                status,
+                message,
+            } => write!(
+                f,
+                "cannot format {}: blackd does not support this client's protocol version ({}){}",
+                path.display(),
+                status,
+                details(message)
+            ),
+            BlackError::UnexpectedStatus {
+                path,
+                status,
+                message,
+            } => write!(
+                f,
+                "cannot format {}: blackd returned an unrecognized status code ({}){}",
+                path.display(),
+                status,
+                details(message)
+            ),
+            BlackError::Encoding { path, message } => {
+                write!(f, "cannot format {}: {}", path.display(), message)
+            }
+            BlackError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl Error for BlackError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BlackError::Connection { source, .. } | BlackError::Request { source } => Some(source),
+            BlackError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for BlackError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() {
+            BlackError::Connection {
+                url: err
+                    .url()
+                    .map(|url| url.to_string())
+                    .unwrap_or_else(|| "<unknown>".to_string()),
+                source: err,
+            }
+        } else {
+            BlackError::Request { source: err }
+        }
+    }
+}
+
+/// reqwest nests the interesting part (e.g. "Connection refused") a few errors deep
+fn root_cause(err: &(dyn Error + 'static)) -> String {
+    let mut cause = err;
+
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+
+    cause.to_string()
+}
+
+fn details(message: &str) -> String {
+    if message.trim().is_empty() {
+        String::new()
+    } else {
+        format!(": {}", message.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_blackd_couldnt_format_exit_with_123() {
+        let path = PathBuf::from("test.py");
+        let message = String::new();
+
+        let failures = [
+            BlackError::Syntax {
+                path: path.clone(),
+                message: message.clone(),
+            },
+            BlackError::Internal {
+                path: path.clone(),
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                message: message.clone(),
+            },
+            BlackError::UnexpectedStatus {
+                path: path.clone(),
+                status: StatusCode::IM_A_TEAPOT,
+                message: message.clone(),
+            },
+            BlackError::Encoding { path, message },
+        ];
+
+        for err in &failures {
+            assert_eq!(err.exit_code(), 123, "{:?}", err);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+
+pub fn read_pyfile(filepath: &Path) -> io::Result<Vec<u8>> {
+    // Grab a read-handle for the specified file
+    let mut origin: fs::File = fs::OpenOptions::new().read(true).open(filepath)?;
+
+    // Setup a mutable buffer for the file's contents
+    let mut file_bytes: Vec<u8> = Vec::new();
+
+    // Read the filelist into the buffer
+    Read::by_ref(&mut origin).read_to_end(&mut file_bytes)?;
+
+    // Return the read bytes
+    Ok(file_bytes)
+}
+
+pub fn write_pyfile(filepath: &Path, data: Vec<u8>) -> io::Result<()> {
+    let original = fs::metadata(filepath)?;
+
+    // The temporary file has to live next to the target, renaming across filesystems fails with EXDEV
+    let directory = match filepath.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // Setup a temporary, writable file to dump the supplied data into
+    let mut temp = match NamedTempFile::new_in(directory) {
+        Ok(temp) => temp,
+        Err(_) => return overwrite_pyfile(filepath, &data),
+    };
+
+    // Dump the supplied data to disk
+    temp.write_all(&data)?;
+
+    // If the replacement can't look exactly like the original, fall back to rewriting the original
+    if copy_metadata(temp.as_file(), filepath, &original).is_err() {
+        return overwrite_pyfile(filepath, &data);
+    }
+
+    temp.as_file().sync_all()?;
+
+    // Replace the specified file with the written one
+    match temp.persist(filepath) {
+        Ok(_) => {
+            sync_directory(directory);
+            Ok(())
+        }
+        Err(_) => overwrite_pyfile(filepath, &data),
+    }
+}
+
+/// Rewrite the specified file's contents in place, which keeps its mode,
+/// ownership and extended attributes but isn't atomic
+fn overwrite_pyfile(filepath: &Path, data: &[u8]) -> io::Result<()> {
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(filepath)?;
+
+    target.write_all(data)?;
+    target.sync_all()
+}
+
+#[cfg(unix)]
+fn copy_metadata(temp: &fs::File, filepath: &Path, original: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    use xattr::FileExt;
+
+    temp.set_permissions(original.permissions())?;
+
+    // Only bother changing ownership if it would otherwise change
+    let current = temp.metadata()?;
+
+    if current.uid() != original.uid() || current.gid() != original.gid() {
+        fchown(temp, Some(original.uid()), Some(original.gid()))?;
+    }
+
+    if xattr::SUPPORTED_PLATFORM {
+        for name in xattr::list(filepath)? {
+            if let Some(value) = xattr::get(filepath, &name)? {
+                temp.set_xattr(&name, &value)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_metadata(temp: &fs::File, _filepath: &Path, original: &fs::Metadata) -> io::Result<()> {
+    temp.set_permissions(original.permissions())
+}
+
+/// Make the rename itself durable, on platforms where that's possible
+fn sync_directory(directory: &Path) {
+    #[cfg(unix)]
+    if let Ok(directory) = fs::File::open(directory) {
+        let _ = directory.sync_all();
+    }
+
+    #[cfg(not(unix))]
+    let _ = directory;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_data_byte_for_byte() {
+        let directory = tempfile::tempdir().unwrap();
+        let filepath = directory.path().join("source.py");
+        fs::write(&filepath, b"x = 1\n").unwrap();
+
+        let data = b"\xef\xbb\xbf# coding: utf-8\r\nx = \"\xc3\xa9\"\r\n".to_vec();
+        write_pyfile(&filepath, data.clone()).unwrap();
+
+        assert_eq!(read_pyfile(&filepath).unwrap(), data);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let filepath = directory.path().join("script.py");
+        fs::write(&filepath, b"x = 1\n").unwrap();
+        fs::set_permissions(&filepath, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write_pyfile(&filepath, b"x = 2\n".to_vec()).unwrap();
+
+        let mode = fs::metadata(&filepath).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
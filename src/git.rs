@@ -0,0 +1,312 @@
+use crate::canonical_path;
+use blackd_client::{BlackError, LineRange};
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The lines changed in every file since the branch forked off `rev` (including uncommitted
+/// changes), keyed by canonical path. Untracked files count as changed in their entirety,
+/// i.e. without ranges.
+pub fn changed_lines(
+    root: &Path,
+    rev: &str,
+) -> Result<HashMap<PathBuf, Vec<LineRange>>, BlackError> {
+    let toplevel = PathBuf::from(git(root, &["rev-parse", "--show-toplevel"])?.trim_end());
+
+    // The revision is resolved on its own first, so that it can't pass for one of git's options
+    let commit = git(
+        &toplevel,
+        &[
+            "rev-parse",
+            "--verify",
+            "--end-of-options",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )?;
+
+    // Diffing against `rev` itself would count whatever landed upstream since the fork as changed
+    let base = git(&toplevel, &["merge-base", commit.trim_end(), "HEAD"])?;
+
+    // Only added and modified lines matter, deletions leave nothing behind to format.
+    // The prefixes are spelled out as the user's diff.noprefix/mnemonicPrefix would change them.
+    let diff = git(
+        &toplevel,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "diff",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "--src-prefix=a/",
+            "--dst-prefix=b/",
+            "--diff-filter=d",
+            base.trim_end(),
+            "--",
+        ],
+    )?;
+
+    let mut changed: HashMap<PathBuf, Vec<LineRange>> = parse_diff(&diff)
+        .into_iter()
+        .map(|(path, ranges)| (canonical_path(&toplevel.join(path)), ranges))
+        .collect();
+
+    let untracked = git(
+        &toplevel,
+        &["ls-files", "-z", "--others", "--exclude-standard"],
+    )?;
+
+    for path in untracked.split('\0').filter(|path| !path.is_empty()) {
+        changed.insert(canonical_path(&toplevel.join(path)), Vec::new());
+    }
+
+    Ok(changed)
+}
+
+/// The lines added or modified in every file of a `git diff --unified=0`, keyed by
+/// the path relative to the repository's top level
+fn parse_diff(diff: &str) -> HashMap<PathBuf, Vec<LineRange>> {
+    let hunk = Regex::new(r"^@@ -\d+(?:,\d+)? \+(\d+)(?:,(\d+))? @@").unwrap();
+    let mut changed: HashMap<PathBuf, Vec<LineRange>> = HashMap::new();
+    let mut current: Option<PathBuf> = None;
+
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            current = diff_path(path).and_then(|path| path.strip_prefix("b/").map(PathBuf::from));
+
+            if let Some(path) = &current {
+                changed.entry(path.clone()).or_default();
+            }
+        } else if let (Some(path), Some(found)) = (&current, hunk.captures(line)) {
+            let start: u32 = found[1].parse().unwrap_or(0);
+            let count: u32 = found
+                .get(2)
+                .map_or(Ok(1), |count| count.as_str().parse())
+                .unwrap_or(0);
+
+            if count > 0 {
+                if let Ok(range) = LineRange::new(start, start + count - 1) {
+                    changed.entry(path.clone()).or_default().push(range);
+                }
+            }
+        }
+    }
+
+    // A file whose only change is a deletion has nothing left to format
+    changed.retain(|_, ranges| !ranges.is_empty());
+
+    changed
+}
+
+/// The path in a `---`/`+++` line, which git C-quotes when it has unusual characters
+/// in it and follows with a tab when it has spaces in it
+fn diff_path(path: &str) -> Option<String> {
+    let quoted = match path.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => return Some(path.strip_suffix('\t').unwrap_or(path).to_string()),
+    };
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(String::from_utf8_lossy(&bytes).into_owned()),
+            '\\' => match chars.next()? {
+                'a' => bytes.push(0x07),
+                'b' => bytes.push(0x08),
+                't' => bytes.push(b'\t'),
+                'n' => bytes.push(b'\n'),
+                'v' => bytes.push(0x0b),
+                'f' => bytes.push(0x0c),
+                'r' => bytes.push(b'\r'),
+                // Octal escapes are bytes, so multi-byte characters come in several
+                digit @ '0'..='3' => {
+                    let octal: String = [digit, chars.next()?, chars.next()?].iter().collect();
+                    bytes.push(u8::from_str_radix(&octal, 8).ok()?);
+                }
+                other => {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+                }
+            },
+            c => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+
+    // An unterminated quote isn't something git would write
+    None
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, BlackError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|err| BlackError::Spawn {
+            message: format!("Could not run git: {}", err),
+        })?;
+
+    if !output.status.success() {
+        return Err(BlackError::Config {
+            message: format!(
+                "--changed-since: `git {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> LineRange {
+        LineRange::new(start, end).unwrap()
+    }
+
+    fn ranges(diff: &str, path: &str) -> Option<Vec<LineRange>> {
+        parse_diff(diff).get(Path::new(path)).cloned()
+    }
+
+    #[test]
+    fn collects_added_and_modified_lines() {
+        let diff = "\
+diff --git a/src/app.py b/src/app.py
+index 1111111..2222222 100644
+--- a/src/app.py
++++ b/src/app.py
+@@ -3,2 +3,3 @@ def main():
+@@ -10 +11 @@ def helper():
+@@ -20,0 +22,2 @@ class App:
+";
+
+        assert_eq!(
+            ranges(diff, "src/app.py"),
+            Some(vec![range(3, 5), range(11, 11), range(22, 23)])
+        );
+    }
+
+    #[test]
+    fn skips_deletion_only_hunks() {
+        let diff = "\
+--- a/deleted_lines.py
++++ b/deleted_lines.py
+@@ -4,2 +3,0 @@ def main():
+--- a/mixed.py
++++ b/mixed.py
+@@ -4,2 +3,0 @@ def main():
+@@ -9 +8 @@ def main():
+";
+
+        assert_eq!(ranges(diff, "deleted_lines.py"), None);
+        assert_eq!(ranges(diff, "mixed.py"), Some(vec![range(8, 8)]));
+    }
+
+    #[test]
+    fn uses_the_new_path_of_renamed_files() {
+        let diff = "\
+diff --git a/old.py b/new.py
+similarity index 90%
+rename from old.py
+rename to new.py
+--- a/old.py
++++ b/new.py
+@@ -1 +1 @@
+";
+
+        assert_eq!(ranges(diff, "old.py"), None);
+        assert_eq!(ranges(diff, "new.py"), Some(vec![range(1, 1)]));
+    }
+
+    #[test]
+    fn handles_paths_with_spaces() {
+        let diff = "\
+--- a/a b.py\t
++++ b/a b.py\t
+@@ -1 +1,2 @@
+";
+
+        assert_eq!(ranges(diff, "a b.py"), Some(vec![range(1, 2)]));
+    }
+
+    #[test]
+    fn unquotes_quoted_paths() {
+        let diff = "\
+--- \"a/\\303\\251.py\"
++++ \"b/\\303\\251.py\"
+@@ -2 +2 @@
+--- \"a/say \\\"hi\\\".py\"
++++ \"b/say \\\"hi\\\".py\"
+@@ -1 +1 @@
+";
+
+        assert_eq!(ranges(diff, "\u{e9}.py"), Some(vec![range(2, 2)]));
+        assert_eq!(ranges(diff, "say \"hi\".py"), Some(vec![range(1, 1)]));
+    }
+
+    /// Run git in `dir` for a test, with an identity so that it can commit
+    fn run(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .status()
+            .unwrap();
+
+        assert!(status.success(), "git {:?}", args);
+    }
+
+    #[test]
+    fn diffs_against_the_merge_base() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+
+        run(&root, &["init", "-q", "-b", "main"]);
+        std::fs::write(root.join("mine.py"), "x = 1\n").unwrap();
+        std::fs::write(root.join("theirs.py"), "y = 1\n").unwrap();
+        run(&root, &["add", "-A"]);
+        run(&root, &["commit", "-q", "-m", "initial"]);
+
+        run(&root, &["checkout", "-q", "-b", "feature"]);
+        std::fs::write(root.join("mine.py"), "x = 1\nx = 2\n").unwrap();
+        run(&root, &["commit", "-q", "-am", "mine"]);
+
+        // Upstream moves on without the branch
+        run(&root, &["checkout", "-q", "main"]);
+        std::fs::write(root.join("theirs.py"), "y = 1\ny = 2\n").unwrap();
+        run(&root, &["commit", "-q", "-am", "theirs"]);
+        run(&root, &["checkout", "-q", "feature"]);
+
+        let changed = changed_lines(&root, "main").unwrap();
+
+        assert_eq!(changed.get(&root.join("mine.py")), Some(&vec![range(2, 2)]));
+        assert_eq!(changed.get(&root.join("theirs.py")), None);
+    }
+
+    #[test]
+    fn rejects_options_posing_as_revisions() {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+        let output = root.join("written");
+
+        run(&root, &["init", "-q"]);
+        std::fs::write(root.join("app.py"), "x = 1\n").unwrap();
+        run(&root, &["add", "-A"]);
+        run(&root, &["commit", "-q", "-m", "initial"]);
+
+        let rev = format!("--output={}", output.display());
+
+        assert!(changed_lines(&root, &rev).is_err());
+        assert!(!output.exists());
+    }
+}
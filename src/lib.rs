@@ -0,0 +1,41 @@
+//! A fast, simple client for black[d]
+//!
+//! ```no_run
+//! use blackd_client::{BlackdClient, FormatOptions, FormatOutcome};
+//!
+//! let client = BlackdClient::new("http://localhost:45484/");
+//! let options = FormatOptions::new().line_length(100);
+//!
+//! match client.format_str("greeting = 'hello'\n", &options) {
+//!     Ok(FormatOutcome::Changed(formatted)) => print!("{}", formatted),
+//!     Ok(_) => println!("already well formatted"),
+//!     Err(err) => eprintln!("{}", err),
+//! }
+//! ```
+//!
+//! The `async` feature adds an `AsyncBlackdClient` with the same methods, for use from
+//! within a tokio runtime (where the blocking client would panic).
+
+#[cfg(feature = "async")]
+mod async_client;
+mod client;
+mod encoding;
+mod error;
+mod files;
+mod line_ranges;
+mod notebook;
+mod options;
+mod target_version;
+mod version;
+
+#[cfg(feature = "async")]
+pub use async_client::AsyncBlackdClient;
+pub use client::{is_stub_file, relabel_diff, BlackdClient, FormatOutcome, DEFAULT_URL};
+pub use encoding::{Newline, SourceEncoding};
+pub use error::BlackError;
+pub use files::{read_pyfile, write_pyfile};
+pub use line_ranges::{normalize_line_ranges, LineRange};
+pub use notebook::{is_notebook, NotebookOutcome};
+pub use options::{FormatOptions, DEFAULT_LINE_LENGTH};
+pub use target_version::{parse_py_versions, TargetVersion};
+pub use version::{BlackVersion, Feature};
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A 1-based, inclusive range of lines to format, leaving the rest of the source alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl LineRange {
+    pub fn new(start: u32, end: u32) -> Result<LineRange, String> {
+        if start == 0 {
+            Err(format!(
+                "invalid line range {}-{}: line numbers start at 1",
+                start, end
+            ))
+        } else if end < start {
+            Err(format!(
+                "invalid line range {}-{}: the end can't come before the start",
+                start, end
+            ))
+        } else {
+            Ok(LineRange { start, end })
+        }
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for LineRange {
+    type Err = String;
+
+    /// Accepts black's `START-END` notation
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid line range {:?}, expected START-END", value.trim());
+
+        let (start, end) = value.trim().split_once('-').ok_or_else(invalid)?;
+        let start = start.trim().parse::<u32>().map_err(|_| invalid())?;
+        let end = end.trim().parse::<u32>().map_err(|_| invalid())?;
+
+        LineRange::new(start, end)
+    }
+}
+
+/// Sort the ranges, merging any that overlap or touch
+pub fn normalize_line_ranges(mut ranges: Vec<LineRange>) -> Vec<LineRange> {
+    ranges.sort();
+
+    let mut merged: Vec<LineRange> = Vec::with_capacity(ranges.len());
+
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end: u32) -> LineRange {
+        LineRange::new(start, end).unwrap()
+    }
+
+    #[test]
+    fn parses_start_end() {
+        assert_eq!("3-7".parse::<LineRange>(), Ok(range(3, 7)));
+        assert_eq!(" 3 - 7 ".parse::<LineRange>(), Ok(range(3, 7)));
+        assert_eq!("5-5".parse::<LineRange>(), Ok(range(5, 5)));
+        assert_eq!(range(3, 7).to_string(), "3-7");
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!("0-5"
+            .parse::<LineRange>()
+            .unwrap_err()
+            .contains("start at 1"));
+        assert!("5-3"
+            .parse::<LineRange>()
+            .unwrap_err()
+            .contains("before the start"));
+
+        for value in ["a-b", "5", "-5", "1-", "1-2-3", ""] {
+            let err = value.parse::<LineRange>().unwrap_err();
+
+            assert!(err.contains("expected START-END"), "{}: {}", value, err);
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(
+            normalize_line_ranges(vec![range(10, 12), range(1, 3), range(4, 5), range(2, 3)]),
+            vec![range(1, 5), range(10, 12)]
+        );
+        assert_eq!(
+            normalize_line_ranges(vec![range(1, 10), range(3, 4)]),
+            vec![range(1, 10)]
+        );
+    }
+
+    #[test]
+    fn keeps_separate_ranges_apart() {
+        assert_eq!(
+            normalize_line_ranges(vec![range(5, 6), range(1, 3)]),
+            vec![range(1, 3), range(5, 6)]
+        );
+        assert_eq!(normalize_line_ranges(Vec::new()), Vec::new());
+    }
+}
@@ -1,172 +1,454 @@
 use argh::FromArgs;
 use colored::*;
-use reqwest::blocking::{Client as BlockingClient, RequestBuilder};
-use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::StatusCode;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Certificate, Identity};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
-use std::fmt;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use tempfile::NamedTempFile;
-
-pub const PY27: &'static str = "27";
-pub const PY33: &'static str = "33";
-pub const PY34: &'static str = "34";
-pub const PY35: &'static str = "35";
-pub const PY36: &'static str = "36";
-pub const PY37: &'static str = "37";
-pub const PY38: &'static str = "38";
-pub const PY39: &'static str = "39";
-pub const PYI: &'static str = "PYI";
-
-fn main() -> Result<(), String> {
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+mod config;
+mod daemon;
+mod environment;
+mod git;
+mod origins;
+mod report;
+mod retry;
+mod sources;
+mod workers;
+
+use blackd_client::{
+    is_notebook, is_stub_file, parse_py_versions, read_pyfile, relabel_diff, write_pyfile,
+    BlackError, BlackdClient, Feature, FormatOptions, FormatOutcome, LineRange, TargetVersion,
+};
+use config::{apply_config, find_pyproject_toml, read_pyproject_toml};
+use daemon::ensure_blackd;
+use environment::apply_env;
+use git::changed_lines;
+use origins::{show_config, Origins};
+use report::{color_diff, Changed, Report};
+use retry::Retrier;
+use sources::{
+    check_line_ranges, collect_sources, find_project_root, parse_regex, split_notebooks,
+    SourceFilter,
+};
+use workers::run_in_pool;
+
+const DEFAULT_HOST: &str = "localhost";
+const DEFAULT_PORT: u16 = 45484;
+const DEFAULT_BLACKD_PATH: &str = "blackd";
+
+fn main() {
     // Pull in and parse the arguments
-    let cli_options: CliOptions = argh::from_env();
+    let mut cli_options: CliOptions = cli_options_from_env();
+    let mut origins = Origins::from_cli(&cli_options);
 
-    if cli_options.src.is_empty() {
+    // Anything not given on the command line can come from a BLACKD_* variable
+    if let Err(message) = apply_env(&mut cli_options, &mut origins) {
+        exit_with(BlackError::Config { message });
+    }
+
+    // Without explicit sources, --changed-since looks at the whole working directory
+    if cli_options.src.is_empty() && cli_options.changed_since.is_some() {
+        cli_options.src.push(".".to_string());
+    }
+
+    if cli_options.src.is_empty() && !cli_options.show_config {
         println!("\nError: No target source file(s) specified!\n");
-        return Ok(());
-    }
-
-    // Setup an instance of reqwest's blocking Client
-    let client = BlockingClient::new();
-
-    // Translate the launch arguments into their appropriate headers
-    let headers = headers_from_cli_options(&cli_options);
-
-    let req_builder = (&client)
-        .post(format!(
-            "http://{}:{}/",
-            &(cli_options.host),
-            &(cli_options.port)
-        ))
-        .headers(headers.clone());
-
-    println!("\n");
-
-    let (mut formatted, mut skipped) = (0u32, 0u32);
-
-    for source_file in cli_options.src.iter() {
-        match format_pyfile(
-            source_file,
-            req_builder.try_clone().unwrap_or(
-                (&client)
-                    .post(format!(
-                        "http://{}:{}/",
-                        &(cli_options.host),
-                        &(cli_options.port)
-                    ))
-                    .headers(headers.clone()),
-            ),
+        return;
+    }
+
+    // When formatting stdin, the --stdin-filename stands in for `-` when locating the project
+    let project_root = find_project_root(
+        &cli_options
+            .src
+            .iter()
+            .map(
+                |entry| match (entry.as_str(), &cli_options.stdin_filename) {
+                    ("-", Some(stdin_filename)) => stdin_filename.as_str(),
+                    _ => entry.as_str(),
+                },
+            )
+            .collect::<Vec<&str>>(),
+    );
+
+    // Merge in any [tool.black] settings that weren't overridden on the command line or in the environment
+    let config_file = match &cli_options.config {
+        Some(path) => Some(PathBuf::from(path)),
+        None => find_pyproject_toml(project_root.as_path()),
+    };
+
+    if let Some(config_file) = &config_file {
+        if let Err(message) = read_pyproject_toml(config_file.as_path())
+            .and_then(|config| apply_config(&mut cli_options, &mut origins, &config, config_file))
+        {
+            exit_with(BlackError::Config { message });
+        }
+    }
+
+    if cli_options.show_config {
+        println!("{}", show_config(&cli_options, &origins));
+        return;
+    }
+
+    let write_back = WriteBack::from_cli_options(&cli_options);
+
+    // Launch a local blackd if one was asked for and nothing's listening yet
+    if cli_options.spawn_blackd && (cli_options.socket.is_some() || cli_options.url.is_some()) {
+        exit_with(BlackError::Config {
+            message: "--spawn-blackd can't be combined with --socket or --url, it launches blackd on --host/--port"
+                .to_string(),
+        });
+    }
+
+    let spawned_blackd = if cli_options.spawn_blackd {
+        match ensure_blackd(
+            cli_options.host(),
+            cli_options.port(),
+            cli_options.blackd_path(),
+            cli_options.keep_blackd,
         ) {
-            Ok(success) => {
-                if success {
-                    formatted += 1;
-                } else {
-                    skipped += 1;
+            Ok(spawned) => spawned,
+            Err(err) => exit_with(err),
+        }
+    } else {
+        None
+    };
+
+    let client = match blackd_client_from_cli_options(&cli_options) {
+        Ok(client) => client,
+        Err(err) => {
+            drop(spawned_blackd);
+            exit_with(err);
+        }
+    };
+
+    // Translate the launch arguments into their appropriate formatting options
+    let options = format_options_from_cli_options(&cli_options);
+
+    if cli_options.verbose {
+        eprintln!("Using blackd at {}", client.url());
+
+        if let Some(config_file) = &config_file {
+            eprintln!("Using configuration from {}", config_file.display());
+        }
+    }
+
+    if cli_options.version_check || cli_options.verbose {
+        let mut features = options.features();
+
+        if cli_options.changed_since.is_some() {
+            features.push(Feature::LineRanges);
+        }
+
+        // The probe gets its own retrier, it isn't one of the files that needed retrying
+        let retrier = Retrier::new(cli_options.retries.unwrap_or(0));
+
+        match check_blackd_version(&client, &features, cli_options.verbose, &retrier) {
+            Ok(()) => {}
+            // --verbose alone is only diagnostic, it shouldn't fail a run that would otherwise succeed
+            Err(err) if !cli_options.version_check => eprintln!(
+                "{} couldn't ask blackd for its black version: {}",
+                "warning:".yellow(),
+                err
+            ),
+            Err(err) => {
+                drop(spawned_blackd);
+                exit_with(err);
+            }
+        }
+    }
+
+    eprintln!("\n");
+
+    let mut report = Report::new(cli_options.check, cli_options.diff);
+
+    let source_filter = SourceFilter::new(
+        project_root.clone(),
+        cli_options.include.clone(),
+        cli_options.exclude.clone(),
+        cli_options.extend_exclude.clone(),
+        cli_options.force_exclude.clone(),
+    );
+
+    let stdin_filename = cli_options.stdin_filename.as_ref().map(PathBuf::from);
+
+    let mut sources = collect_sources(&cli_options.src, stdin_filename.as_deref(), &source_filter);
+
+    // Only the sources git knows to have changed are formatted, and only where they changed
+    let changed = match &cli_options.changed_since {
+        Some(_) if !cli_options.line_ranges.is_empty() => {
+            drop(spawned_blackd);
+            exit_with(BlackError::Config {
+                message: "--changed-since and --line-ranges can't be combined".to_string(),
+            });
+        }
+        Some(_) if cli_options.src.iter().any(|entry| entry == "-") => {
+            drop(spawned_blackd);
+            exit_with(BlackError::Config {
+                message: "--changed-since can't be used when formatting stdin".to_string(),
+            });
+        }
+        Some(rev) => match changed_lines(&project_root, rev) {
+            Ok(changed) => {
+                sources.retain(|source_file| changed.contains_key(&canonical_path(source_file)));
+
+                // A notebook's changed lines can't be mapped to its cells, so it's left alone
+                let (python_sources, notebooks) = split_notebooks(sources);
+                sources = python_sources;
+
+                for notebook in notebooks {
+                    eprintln!(
+                        "warning: skipping {}, --changed-since can't tell which of its cells changed",
+                        notebook.display()
+                    );
+                }
+
+                if cli_options.verbose {
+                    eprintln!("{} changed since {}", pluralize_files(sources.len()), rev);
                 }
+
+                Some(changed)
             }
             Err(err) => {
-                skipped += 1;
-                println!("{}", err);
+                drop(spawned_blackd);
+                exit_with(err);
             }
+        },
+        None => None,
+    };
+
+    if !cli_options.line_ranges.is_empty() {
+        if let Err(message) = check_line_ranges(&sources, stdin_filename.as_deref()) {
+            drop(spawned_blackd);
+            exit_with(BlackError::Config { message });
         }
     }
 
-    let mut results: String = "\nAll done! ✨ 🍰 ✨\n".yellow().to_string();
-
-    if formatted == 1 {
-        results += "\n• ".yellow().to_string().as_str();
-        results += "1 file reformatted".green().to_string().as_str();
-    } else if formatted > 1 {
-        results += "\n• ".yellow().to_string().as_str();
-        results += format!("{} files reformatted", formatted)
-            .green()
-            .to_string()
-            .as_str();
+    // A force-excluded stdin is echoed back untouched, so editors don't lose their buffer
+    if write_back == WriteBack::Yes
+        && cli_options.src.iter().any(|entry| entry == "-")
+        && !sources
+            .iter()
+            .any(|source_file| source_file.as_os_str() == "-")
+    {
+        if let Err(err) = io::copy(&mut io::stdin().lock(), &mut io::stdout().lock()) {
+            report.failed(&BlackError::io("-", err));
+        }
     }
 
-    if skipped == 1 {
-        results += "\n• ".red().to_string().as_str();
-        results += "1 file left unchanged.".yellow().to_string().as_str();
-    } else if skipped > 1 {
-        results += "\n• ".red().to_string().as_str();
-        results += format!("{} files left unchanged.", skipped)
-            .yellow()
-            .to_string()
-            .as_str();
+    let workers = cli_options.workers.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+    });
+
+    let retrier = Retrier::new(cli_options.retries.unwrap_or(0));
+
+    // Files are sent off concurrently, stdin is left for the main thread
+    let results = run_in_pool(&sources, workers, |source_file| {
+        if source_file.as_os_str() == "-" {
+            None
+        } else {
+            Some(format_pyfile(
+                source_file,
+                &client,
+                &options_for(&options, changed.as_ref(), source_file),
+                write_back,
+                &retrier,
+            ))
+        }
+    });
+
+    // Results are reported in the order the sources were collected, however they finished
+    for (source_file, result) in sources.iter().zip(results) {
+        let result = result.unwrap_or_else(|| {
+            format_stdin(
+                stdin_filename.as_deref(),
+                &client,
+                &options,
+                write_back,
+                &retrier,
+            )
+        });
+
+        match result {
+            Ok(changed) => report.done(source_file, changed),
+            // Every other file would be rejected the same way
+            Err(err @ BlackError::RejectedOption { .. }) => {
+                drop(spawned_blackd);
+                exit_with(err);
+            }
+            Err(err) => report.failed(&err),
+        }
     }
 
-    results += "\n";
+    report.retried(retrier.retried_count());
 
-    println!("{}", results);
+    eprintln!("{}", report.summary());
 
-    Ok(())
-}
+    // process::exit skips destructors, so any blackd we launched has to be shut down first
+    drop(spawned_blackd);
 
-#[derive(Debug)]
-struct BlackError {
-    what_happened: String,
+    process::exit(report.return_code());
 }
 
-impl fmt::Display for BlackError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.what_happened.as_str())
-    }
+fn exit_with(err: BlackError) -> ! {
+    eprintln!("\n{} {}\n", "Error:".red(), err);
+    process::exit(err.exit_code());
 }
 
-impl BlackError {
-    fn from_debug<T: fmt::Debug>(err: T) -> BlackError {
-        BlackError {
-            what_happened: format!("{:#?}", err),
-        }
-    }
-}
+/// The equivalent of `argh::from_env`, except that a bare `-` is accepted as a
+/// source (argh would otherwise reject it as an unrecognized argument)
+fn cli_options_from_env() -> CliOptions {
+    let args: Vec<String> = env::args().collect();
 
-impl From<std::io::Error> for BlackError {
-    fn from(err: std::io::Error) -> Self {
-        BlackError::from_debug(err)
+    let cmd = Path::new(&args[0])
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(args[0].as_str());
+
+    // Any `-` before a `--` gets moved after it, where argh will take it as a positional
+    let split = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg == "--")
+        .map(|idx| idx + 1)
+        .unwrap_or(args.len());
+
+    let (stdin, mut rearranged): (Vec<&str>, Vec<&str>) = args[1..split]
+        .iter()
+        .map(|arg| arg.as_str())
+        .partition(|arg| *arg == "-");
+
+    if !stdin.is_empty() || split < args.len() {
+        rearranged.push("--");
+        rearranged.extend(stdin);
+        rearranged.extend(args.iter().skip(split + 1).map(|arg| arg.as_str()));
     }
+
+    CliOptions::from_args(&[cmd], &rearranged).unwrap_or_else(|early_exit| {
+        process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!("{}", early_exit.output);
+                1
+            }
+        })
+    })
 }
 
-impl From<reqwest::Error> for BlackError {
-    fn from(err: reqwest::Error) -> Self {
-        BlackError::from_debug(err)
-    }
+/// What to do with blackd's response for each source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteBack {
+    /// write the reformatted code back to the source file
+    Yes,
+    /// leave the source file alone, only report whether it would change
+    Check,
+    /// leave the source file alone and print a diff of the changes to stdout
+    Diff,
+    /// like `Diff`, but colorized
+    ColorDiff,
 }
 
-impl From<std::string::FromUtf8Error> for BlackError {
-    fn from(err: std::string::FromUtf8Error) -> Self {
-        BlackError::from_debug(err)
+impl WriteBack {
+    fn from_cli_options(options: &CliOptions) -> WriteBack {
+        if options.diff && options.color {
+            WriteBack::ColorDiff
+        } else if options.diff {
+            WriteBack::Diff
+        } else if options.check {
+            WriteBack::Check
+        } else {
+            WriteBack::Yes
+        }
     }
 }
 
-impl Error for BlackError {}
-
 #[derive(FromArgs, Debug, Default)]
 /// black: The uncompromising code formatter
 struct CliOptions {
     /// the address of the local blackd server [default: localhost]
-    #[argh(option, short = 'h', default = "\"localhost\".to_string()")]
-    host: String,
+    #[argh(option, short = 'h')]
+    host: Option<String>,
 
     /// the port the local blackd server is listening on [default: 45484]
-    #[argh(option, short = 'p', default = "45484u16")]
-    port: u16,
+    #[argh(option, short = 'p')]
+    port: Option<u16>,
+
+    /// the path of a unix socket blackd is listening on, used instead of --host/--port (also accepts unix:///path)
+    #[argh(option)]
+    socket: Option<String>,
+
+    /// blackd's full address, including its scheme and any path prefix, e.g. https://tools.example/blackd/ (overrides --host and --port)
+    #[argh(option)]
+    url: Option<String>,
+
+    /// a PEM bundle of CA certificates to trust (besides the system's) when connecting over https
+    #[argh(option)]
+    ca_cert: Option<String>,
+
+    /// a PEM client certificate to present when connecting over https, optionally including its private key
+    #[argh(option)]
+    client_cert: Option<String>,
+
+    /// the PEM private key for --client-cert, if it isn't in the certificate file
+    #[argh(option)]
+    client_key: Option<String>,
+
+    /// an extra "Name: value" header to send to blackd, can be repeated (a bearer token can also be set via BLACKD_TOKEN)
+    #[argh(option)]
+    header: Vec<String>,
+
+    /// if nothing is listening on --host/--port, launch blackd there before formatting [default: false]
+    #[argh(switch)]
+    spawn_blackd: bool,
+
+    /// the blackd executable used by --spawn-blackd [default: blackd]
+    #[argh(option)]
+    blackd_path: Option<String>,
+
+    /// leave a blackd launched by --spawn-blackd running for subsequent invocations [default: false]
+    #[argh(switch)]
+    keep_blackd: bool,
+
+    /// ask blackd for its black version before formatting, warning about requested options it doesn't support [default: false]
+    #[argh(switch)]
+    version_check: bool,
+
+    /// print which blackd and configuration are being used, including blackd's black version [default: false]
+    #[argh(switch, short = 'v')]
+    verbose: bool,
+
+    /// read configuration from the specified toml file instead of the project's pyproject.toml [default: <project root>/pyproject.toml]
+    #[argh(option)]
+    config: Option<String>,
+
+    /// print the effective configuration, and where each value came from, then exit [default: false]
+    #[argh(switch)]
+    show_config: bool,
 
     /// how many characters per line to allow [default: 88]
-    #[argh(option, short = 'l', default = "88")]
-    line_length: u8,
-
-    /// python versions that should be supported by Black's output [default: per-file auto-detection]
-    #[argh(
-        option,
-        short = 't',
-        default = "\"\".to_string()",
-        from_str_fn(parse_py_versions)
-    )]
-    target_version: String,
+    #[argh(option, short = 'l')]
+    line_length: Option<u8>,
+
+    /// comma-separated python versions that should be supported by Black's output, e.g. py311 or 3.11 [default: per-file auto-detection]
+    #[argh(option, short = 't', from_str_fn(parse_py_versions))]
+    target_version: Option<Vec<TargetVersion>>,
+
+    /// format all input files like typing stubs regardless of file extension, useful when piping source on stdin [default: auto-detected from the .pyi extension]
+    #[argh(switch)]
+    pyi: bool,
 
     /// don't normalize string quotes or prefixes [default: false]
     #[argh(switch, short = 'S')]
@@ -176,6 +458,30 @@ struct CliOptions {
     #[argh(switch, short = 'C')]
     skip_magic_trailing_comma: bool,
 
+    /// leave the first line of each source alone, e.g. a non-python shebang wrapper [default: false]
+    #[argh(switch, short = 'x')]
+    skip_source_first_line: bool,
+
+    /// enable potentially disruptive style changes that may be added to black's main functionality in the next major release [default: false]
+    #[argh(switch)]
+    preview: bool,
+
+    /// enable all preview style features, including the unstable ones (implies --preview) [default: false]
+    #[argh(switch)]
+    unstable: bool,
+
+    /// enable a specific unstable preview feature by name, can be repeated
+    #[argh(option, from_str_fn(parse_unstable_feature))]
+    enable_unstable_feature: Vec<String>,
+
+    /// only format the lines in START-END (1-based, inclusive), can be repeated but only used with a single source file [default: the whole file]
+    #[argh(option)]
+    line_ranges: Vec<LineRange>,
+
+    /// only format the lines changed (committed or not) since the branch forked off the git revision, in the sources that changed, e.g. origin/main [default: format everything]
+    #[argh(option)]
+    changed_since: Option<String>,
+
     /// if --fast is given, skip temporary sanity checks [default: --safe]
     #[argh(switch)]
     fast: bool,
@@ -184,180 +490,425 @@ struct CliOptions {
     #[argh(switch)]
     safe: bool,
 
+    /// if present, the target source files will not be altered, and the exit code will be 1 if any of them would be reformatted (or 123 if any of them failed) [default: false]
+    #[argh(switch)]
+    check: bool,
+
     /// if present, the target source files will not be altered and a diff of the formats will be output instead [default: false]
     #[argh(switch)]
     diff: bool,
 
-    /// the source file(s) to be formatted [required]
+    /// if present, the output of --diff will be colorized [default: false]
+    #[argh(switch)]
+    color: bool,
+
+    /// a regular expression that matches files and directories that should be included on recursive searches [default: (\.pyi?|\.ipynb)$]
+    #[argh(option, from_str_fn(parse_regex))]
+    include: Option<Regex>,
+
+    /// a regular expression that matches files and directories that should be excluded on recursive searches, replacing the default exclusions and .gitignore [default: common vcs, virtualenv and build directories, plus .gitignore]
+    #[argh(option, from_str_fn(parse_regex))]
+    exclude: Option<Regex>,
+
+    /// like --exclude, but adds additional files and directories on top of the excluded ones
+    #[argh(option, from_str_fn(parse_regex))]
+    extend_exclude: Option<Regex>,
+
+    /// like --exclude, but files and directories matching this regex will be excluded even when they are passed explicitly as arguments
+    #[argh(option, from_str_fn(parse_regex))]
+    force_exclude: Option<Regex>,
+
+    /// how many seconds to wait for blackd to format a file before giving up on it, 0 waits forever [default: 30]
+    #[argh(option, from_str_fn(parse_seconds))]
+    timeout: Option<Duration>,
+
+    /// how many seconds to wait for a connection to blackd, 0 waits forever [default: no timeout]
+    #[argh(option, from_str_fn(parse_seconds))]
+    connect_timeout: Option<Duration>,
+
+    /// how often to retry a file, with exponential backoff, when blackd can't be reached or has a server error (syntax errors are never retried) [default: 0]
+    #[argh(option)]
+    retries: Option<u32>,
+
+    /// the maximum number of files to send to blackd concurrently [default: number of cpus]
+    #[argh(option, short = 'W')]
+    workers: Option<usize>,
+
+    /// the name of the file being formatted when reading from stdin, used for exclusion rules and diff labels
+    #[argh(option)]
+    stdin_filename: Option<String>,
+
+    /// the source file(s) and/or directories to be formatted, directories are searched recursively for .py, .pyi and .ipynb files, and `-` reads from stdin and writes to stdout [required]
     #[argh(positional)]
     src: Vec<String>,
 }
 
-fn parse_py_versions(version_string: &str) -> Result<String, String> {
-    let supported_versions = vec![PY27, PY33, PY34, PY35, PY36, PY37, PY38, PY39, PYI];
+impl CliOptions {
+    fn host(&self) -> &str {
+        self.host.as_deref().unwrap_or(DEFAULT_HOST)
+    }
 
-    let versions: Vec<String> = version_string
-        .split(',')
-        .filter(|entry| entry.len() > 0)
-        .map(|item| item.chars().filter(|x| x.is_numeric()).collect())
-        .collect::<Vec<String>>()
-        .iter()
-        .filter(|entry| supported_versions.contains(&&***entry))
-        .map(|val| format!("py{}", val))
-        .collect::<Vec<String>>();
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
 
-    Ok(versions.join(",").to_string())
+    fn blackd_path(&self) -> &str {
+        self.blackd_path.as_deref().unwrap_or(DEFAULT_BLACKD_PATH)
+    }
 }
 
-fn headers_from_cli_options(options: &CliOptions) -> HeaderMap {
-    let mut headers = HeaderMap::new();
-    let line_length = (&options.line_length).to_string();
+/// Warn about any requested features the running blackd is too old to understand, as
+/// it would silently ignore them
+fn check_blackd_version(
+    client: &BlackdClient,
+    features: &[Feature],
+    verbose: bool,
+    retrier: &Retrier,
+) -> Result<(), BlackError> {
+    let version = match retrier.run(Path::new(client.url()), || client.probe())? {
+        Some(version) => version,
+        None => {
+            eprintln!(
+                "{} blackd didn't report its black version, it may not support every requested option",
+                "warning:".yellow()
+            );
+            return Ok(());
+        }
+    };
 
-    // X-Protocol-Version
-    headers.insert("X-Protocol-Version", HeaderValue::from_str("1").unwrap());
+    if verbose {
+        eprintln!("blackd is running black {}", version);
+    }
 
-    // X-Line-Length
-    headers.insert(
-        "X-Line-Length",
-        HeaderValue::from_str(line_length.as_str()).unwrap(),
-    );
+    for feature in features {
+        if !version.supports(*feature) {
+            eprintln!(
+                "{} blackd is running black {}, which ignores {} (added in black {})",
+                "warning:".yellow(),
+                version,
+                feature,
+                feature.since()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn blackd_url(options: &CliOptions) -> Result<String, BlackError> {
+    match (&options.url, &options.socket) {
+        (Some(_), Some(_)) => Err(BlackError::Config {
+            message: "--url and --socket can't be combined".to_string(),
+        }),
+        (Some(url), None) => match url.split_once("://") {
+            Some(("http" | "https" | "unix", _)) => Ok(url.clone()),
+            _ => Err(BlackError::Config {
+                message: format!(
+                    "Invalid --url {:?}, expected an http://, https:// or unix:// URL",
+                    url
+                ),
+            }),
+        },
+        (None, Some(socket)) if socket.starts_with("unix://") => Ok(socket.clone()),
+        (None, Some(socket)) => Ok(format!("unix://{}", socket)),
+        (None, None) => Ok(format!("http://{}:{}/", options.host(), options.port())),
+    }
+}
+
+fn blackd_client_from_cli_options(options: &CliOptions) -> Result<BlackdClient, BlackError> {
+    let url = blackd_url(options)?;
+    let mut builder = BlackdClient::http_client_builder(&url);
+
+    // A timeout of 0 turns off reqwest's own 30s default, large files can take longer than that
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(Some(timeout).filter(|timeout| !timeout.is_zero()));
+    }
 
-    // X-Skip-String-Normalization
-    if options.skip_string_normalization {
-        headers.insert(
-            "X-Skip-String-Normalization",
-            HeaderValue::from_str("true").unwrap(),
-        );
+    if let Some(connect_timeout) = options.connect_timeout {
+        builder =
+            builder.connect_timeout(Some(connect_timeout).filter(|timeout| !timeout.is_zero()));
     }
 
-    // X-Skip-Magic-Trailing-Comma
-    if options.skip_magic_trailing_comma {
-        headers.insert(
-            "X-Skip-Magic-Trailing-Comma",
-            HeaderValue::from_str("true").unwrap(),
-        );
+    // Trust the CA bundle's certificates on top of the system's
+    if let Some(ca_cert) = &options.ca_cert {
+        let bundle = fs::read(ca_cert).map_err(|err| BlackError::io(ca_cert, err))?;
+
+        let certificates = match Certificate::from_pem_bundle(&bundle) {
+            Ok(certificates) if !certificates.is_empty() => certificates,
+            Ok(_) => {
+                return Err(BlackError::Config {
+                    message: format!("Invalid --ca-cert {}: no certificates found", ca_cert),
+                })
+            }
+            Err(err) => return Err(tls_config_error("--ca-cert", ca_cert, err)),
+        };
+
+        builder = builder.tls_certs_merge(certificates);
     }
 
-    // X-Fast-Or-Safe
-    if options.fast && !options.safe {
-        headers.insert("X-Fast-Or-Safe", HeaderValue::from_str("fast").unwrap());
+    // rustls wants the client certificate and its key in a single PEM buffer
+    match (&options.client_cert, &options.client_key) {
+        (Some(client_cert), client_key) => {
+            let mut pem = fs::read(client_cert).map_err(|err| BlackError::io(client_cert, err))?;
+
+            if let Some(client_key) = client_key {
+                pem.push(b'\n');
+                pem.extend(fs::read(client_key).map_err(|err| BlackError::io(client_key, err))?);
+            }
+
+            let identity = Identity::from_pem(&pem)
+                .map_err(|err| tls_config_error("--client-cert", client_cert, err))?;
+
+            builder = builder.identity(identity);
+        }
+        (None, Some(_)) => {
+            return Err(BlackError::Config {
+                message: "--client-key requires --client-cert".to_string(),
+            })
+        }
+        (None, None) => {}
+    }
+
+    let http = builder.build().map_err(|err| BlackError::Config {
+        message: format!("Could not set up a connection to blackd: {}", err),
+    })?;
+
+    Ok(BlackdClient::with_http_client(http, url).with_headers(extra_headers(options)?))
+}
+
+/// black's feature names are all snake_case identifiers
+fn parse_unstable_feature(value: &str) -> Result<String, String> {
+    let feature = value.trim();
+
+    if !feature.is_empty()
+        && feature
+            .chars()
+            .all(|char| char.is_ascii_lowercase() || char.is_ascii_digit() || char == '_')
+    {
+        Ok(feature.to_string())
     } else {
-        headers.insert("X-Fast-Or-Safe", HeaderValue::from_str("safe").unwrap());
+        Err(format!("invalid unstable feature name {:?}", value))
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("expected a number of seconds (0 for none), got {:?}", value))
+}
+
+fn tls_config_error(option: &str, path: &str, err: reqwest::Error) -> BlackError {
+    // reqwest's own message is just "builder error", the reason is in its source
+    let reason = match err.source() {
+        Some(source) => source.to_string(),
+        None => err.to_string(),
+    };
+
+    BlackError::Config {
+        message: format!("Invalid {} {}: {}", option, path, reason),
     }
+}
+
+fn extra_headers(options: &CliOptions) -> Result<HeaderMap, BlackError> {
+    let mut headers = HeaderMap::new();
+
+    for header in &options.header {
+        let invalid = || BlackError::Config {
+            message: format!("Invalid --header {:?}, expected \"Name: value\"", header),
+        };
 
-    // X-Python-Variant
-    if !options.target_version.is_empty() {
-        headers.insert(
-            "X-Python-Variant",
-            HeaderValue::from_str(&options.target_version).unwrap(),
-        );
+        let (name, value) = header.split_once(':').ok_or_else(invalid)?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| invalid())?;
+
+        headers.append(name, value);
     }
 
-    // X-Diff
-    if options.diff {
-        headers.insert("X-Diff", HeaderValue::from_str("true").unwrap());
+    // The token comes from the environment to keep it out of the process list, an explicit header wins
+    if let Ok(token) = env::var("BLACKD_TOKEN") {
+        if !token.trim().is_empty() && !headers.contains_key(AUTHORIZATION) {
+            let mut value =
+                HeaderValue::from_str(&format!("Bearer {}", token.trim())).map_err(|_| {
+                    BlackError::Config {
+                        message: "BLACKD_TOKEN contains characters that aren't allowed in a header"
+                            .to_string(),
+                    }
+                })?;
+
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
     }
 
-    headers
+    Ok(headers)
 }
 
-fn read_pyfile(filepath: &Path) -> Result<Vec<u8>, BlackError> {
-    // Grab a read-handle for the specified file
-    let mut origin: fs::File = fs::OpenOptions::new().read(true).open(filepath)?;
+fn format_options_from_cli_options(options: &CliOptions) -> FormatOptions {
+    let mut format_options = FormatOptions::new()
+        .target_versions(options.target_version.clone().unwrap_or_default())
+        .pyi(options.pyi)
+        .skip_string_normalization(options.skip_string_normalization)
+        .skip_magic_trailing_comma(options.skip_magic_trailing_comma)
+        .skip_source_first_line(options.skip_source_first_line)
+        .preview(options.preview)
+        .unstable(options.unstable)
+        .line_ranges(options.line_ranges.clone())
+        .fast(options.fast && !options.safe)
+        .diff(options.diff);
+
+    for feature in &options.enable_unstable_feature {
+        format_options = format_options.enable_unstable_feature(feature.as_str());
+    }
 
-    // Setup a mutable buffer for the file's contents
-    let mut file_bytes: Vec<u8> = Vec::new();
+    if let Some(line_length) = options.line_length {
+        format_options = format_options.line_length(line_length);
+    }
 
-    // Read the filelist into the buffer
-    Read::by_ref(&mut origin).read_to_end(&mut file_bytes)?;
+    format_options
+}
 
-    // Return the read bytes
-    Ok(file_bytes)
+/// With --changed-since, each file is only formatted where it changed (untracked files entirely)
+fn options_for<'a>(
+    options: &'a FormatOptions,
+    changed: Option<&HashMap<PathBuf, Vec<LineRange>>>,
+    source_file: &Path,
+) -> Cow<'a, FormatOptions> {
+    match changed.and_then(|changed| changed.get(&canonical_path(source_file))) {
+        Some(line_ranges) => Cow::Owned(options.clone().line_ranges(line_ranges.clone())),
+        None => Cow::Borrowed(options),
+    }
 }
 
-fn write_pyfile(filepath: &Path, data: Vec<u8>) -> Result<bool, BlackError> {
-    // Setup a temporary, writable file to dump the supplied data into
-    let mut temp = NamedTempFile::new()?;
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
 
-    // Dump the supplied data to disk
-    if temp.write_all(&data).is_err() {
-        return Err(BlackError {
-            what_happened: "Could not persist reformatted code to disk!".to_string(),
-        });
+fn pluralize_files(count: usize) -> String {
+    if count == 1 {
+        "1 file".to_string()
+    } else {
+        format!("{} files", count)
     }
+}
 
-    // Replace the specified file with the written one
-    return match temp.persist(filepath) {
-        Ok(_) => Ok(true),
-        Err(err) => Err(BlackError {
-            what_happened: format!("{}", err),
-        }),
-    };
+fn diff_for(write_back: WriteBack, diff: String) -> Changed {
+    if write_back == WriteBack::ColorDiff {
+        Changed::Diff(color_diff(diff.as_str()))
+    } else {
+        Changed::Diff(diff)
+    }
 }
 
-fn format_pyfile<T: AsRef<str>>(filepath: T, client: RequestBuilder) -> Result<bool, BlackError> {
-    let filepath = PathBuf::from(filepath.as_ref())
+fn format_pyfile<T: AsRef<Path>>(
+    filepath: T,
+    client: &BlackdClient,
+    options: &FormatOptions,
+    write_back: WriteBack,
+    retrier: &Retrier,
+) -> Result<Changed, BlackError> {
+    let filepath = filepath
+        .as_ref()
         .canonicalize()
         .unwrap_or_else(|_| PathBuf::from(filepath.as_ref()));
 
-    if !filepath.exists() {
-        return Ok(false);
-    }
-
-    let client = client.body(read_pyfile(filepath.as_path())?);
-
-    let resp = client.send()?;
-
-    match resp.status() {
-        StatusCode::OK => {
-            return match write_pyfile(filepath.as_path(), resp.bytes().unwrap().to_vec()) {
-                Ok(val) => {
-                    if val {
-                        println!(
-                            "{} {}",
-                            "Successfully reformatted".green(),
-                            format!("{:?}", filepath).yellow()
-                        );
-                        Ok(true)
-                    } else {
-                        println!(
-                            "{} {}",
-                            "Could not reformat".red(),
-                            format!("{:?}", filepath).yellow()
-                        );
-                        Ok(false)
-                    }
-                }
-                Err(err) => Err(err),
+    let outcome = if is_notebook(&filepath) {
+        let source = read_pyfile(&filepath).map_err(|err| BlackError::io(&filepath, err))?;
+
+        retrier.run(&filepath, || {
+            format_notebook(client, &source, options, &filepath)
+        })?
+    } else {
+        retrier.run(&filepath, || {
+            client.format_path(filepath.as_path(), options)
+        })?
+    };
+
+    match outcome {
+        FormatOutcome::Unchanged => Ok(Changed::No),
+        FormatOutcome::Changed(formatted) => {
+            if write_back == WriteBack::Yes {
+                write_pyfile(filepath.as_path(), formatted)
+                    .map_err(|err| BlackError::io(&filepath, err))?;
             }
+            Ok(Changed::Yes)
         }
-        StatusCode::NO_CONTENT => {
-            println!(
-                "{} {}",
-                format!("{:?}", filepath).yellow(),
-                "already well formatted, good job.".green()
-            );
-            return Ok(false);
+        FormatOutcome::Diff(diff) => Ok(diff_for(write_back, diff)),
+    }
+}
+
+fn format_stdin(
+    stdin_filename: Option<&Path>,
+    client: &BlackdClient,
+    options: &FormatOptions,
+    write_back: WriteBack,
+    retrier: &Retrier,
+) -> Result<Changed, BlackError> {
+    let label = stdin_filename.unwrap_or_else(|| Path::new("-"));
+
+    // Slurp up everything we've been piped
+    let mut source: Vec<u8> = Vec::new();
+    io::stdin()
+        .lock()
+        .read_to_end(&mut source)
+        .map_err(|err| BlackError::io(label, err))?;
+
+    // An explicit --pyi still wins when the stdin filename isn't a stub
+    let options = if is_stub_file(label) {
+        Cow::Owned(options.clone().pyi(true))
+    } else {
+        Cow::Borrowed(options)
+    };
+
+    let outcome = if is_notebook(label) {
+        retrier.run(label, || format_notebook(client, &source, &options, label))?
+    } else {
+        retrier.run(label, || client.format_bytes(&source, &options))?
+    };
+
+    match outcome {
+        FormatOutcome::Unchanged => {
+            // Editors expect the full buffer back, even when there's nothing to change
+            if write_back == WriteBack::Yes {
+                write_stdout(&source).map_err(|err| BlackError::io(label, err))?;
+            }
+            Ok(Changed::No)
         }
-        StatusCode::BAD_REQUEST => Err(BlackError {
-            what_happened: format!("{:?}", String::from_utf8(resp.bytes().unwrap().to_vec())?)
-                .as_str()
-                .red()
-                .to_string(),
-        }),
-        StatusCode::INTERNAL_SERVER_ERROR => Err(BlackError {
-            what_happened: format!(
-                "{} {}",
-                format!("{:?}", filepath).yellow(),
-                "caused an internal error in `blackd`".red()
-            ),
-        }),
-        _ => Err(BlackError {
-            what_happened: format!(
-                "{} {}",
-                "`blackd` returned an unrecognized status code:".red(),
-                format!("{}", resp.status()).yellow()
-            ),
-        }),
+        FormatOutcome::Changed(formatted) => {
+            if write_back == WriteBack::Yes {
+                write_stdout(&formatted).map_err(|err| BlackError::io(label, err))?;
+            }
+            Ok(Changed::Yes)
+        }
+        // Notebook diffs are already labelled cell by cell
+        FormatOutcome::Diff(diff) if is_notebook(label) => Ok(diff_for(write_back, diff)),
+        FormatOutcome::Diff(diff) => Ok(diff_for(
+            write_back,
+            relabel_diff(&diff, stdin_filename.unwrap_or_else(|| Path::new("STDIN"))),
+        )),
     }
 }
+
+/// Notebooks are formatted cell by cell, with a warning for every cell blackd couldn't parse
+fn format_notebook(
+    client: &BlackdClient,
+    source: &[u8],
+    options: &FormatOptions,
+    filepath: &Path,
+) -> Result<FormatOutcome<Vec<u8>>, BlackError> {
+    let notebook = client.format_notebook(source, options, filepath)?;
+
+    for (_, err) in &notebook.skipped_cells {
+        eprintln!("{} {}, leaving the cell as it is", "warning:".yellow(), err);
+    }
+
+    Ok(notebook.outcome)
+}
+
+fn write_stdout(data: &[u8]) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    stdout.write_all(data)?;
+    stdout.flush()
+}
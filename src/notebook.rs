@@ -0,0 +1,848 @@
+use crate::client::{relabel_diff, FormatOutcome};
+use crate::error::BlackError;
+use crate::options::FormatOptions;
+use serde_json::Value;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Cell magics whose body is still python, anything else (`%%bash`, `%%html`, ...) is left alone
+const PYTHON_CELL_MAGICS: &[&str] = &[
+    "capture", "prun", "pypy", "python", "python3", "time", "timeit",
+];
+
+pub fn is_notebook(filepath: &Path) -> bool {
+    filepath
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("ipynb"))
+        .unwrap_or(false)
+}
+
+/// Each cell is formatted on its own, so options that are about the notebook's file
+/// as a whole (its first line, its extension) don't apply. Line ranges would be lines
+/// of the notebook's JSON rather than of any cell, so they are refused.
+pub(crate) fn cell_options(options: &FormatOptions) -> Result<FormatOptions, BlackError> {
+    if !options.line_ranges.is_empty() {
+        return Err(BlackError::Config {
+            message: "Cannot use --line-ranges with ipynb files".to_string(),
+        });
+    }
+
+    Ok(FormatOptions {
+        pyi: false,
+        skip_source_first_line: false,
+        ..options.clone()
+    })
+}
+
+/// What blackd made of a notebook's code cells
+#[derive(Debug)]
+pub struct NotebookOutcome {
+    /// the reformatted notebook, or the diffs of its cells (one after the other)
+    pub outcome: FormatOutcome<Vec<u8>>,
+    /// the cells (by index) blackd couldn't parse, which are left as they were
+    pub skipped_cells: Vec<(usize, BlackError)>,
+}
+
+/// A parsed `.ipynb` file, written back out the way it was read (key order,
+/// indentation, numbers and all) with only the code cells' sources replaced
+/// in its original text
+pub(crate) struct Notebook {
+    text: String,
+    document: Value,
+    indent: Option<String>,
+    /// where each cell's `source` value is in the text
+    source_spans: Vec<Option<Range<usize>>>,
+    /// the new JSON of the changed cells' sources, in cell order
+    edits: Vec<(Range<usize>, String)>,
+    diffs: Vec<String>,
+    skipped_cells: Vec<(usize, BlackError)>,
+}
+
+/// A code cell's source, ready to be sent to blackd
+pub(crate) struct CodeCell {
+    pub(crate) index: usize,
+    /// the source with its magics masked, ending in a newline
+    pub(crate) source: String,
+    /// the cell's path in diffs and error messages, e.g. `analysis.ipynb:cell_3`
+    pub(crate) label: PathBuf,
+    original: String,
+    masks: Vec<(String, String)>,
+    trailing_semicolon: bool,
+}
+
+impl Notebook {
+    pub(crate) fn parse(source: &[u8], filepath: &Path) -> Result<Notebook, BlackError> {
+        let document: Value = serde_json::from_slice(source).map_err(|err| BlackError::Syntax {
+            path: filepath.to_path_buf(),
+            message: format!("invalid notebook: {}", err),
+        })?;
+
+        if !document.get("cells").is_some_and(Value::is_array) {
+            return Err(BlackError::Syntax {
+                path: filepath.to_path_buf(),
+                message: "invalid notebook: it has no cells".to_string(),
+            });
+        }
+
+        // serde_json only takes UTF-8, so this is the notebook's text as it was
+        let text = String::from_utf8_lossy(source).into_owned();
+        let source_spans = source_spans(&text).ok_or_else(|| BlackError::Syntax {
+            path: filepath.to_path_buf(),
+            message: "invalid notebook: its cells can't be located".to_string(),
+        })?;
+
+        // nbformat indents with a single space, a notebook on one line stays on one line
+        let indent = text.split_once('\n').map(|(_, rest)| {
+            rest.chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect::<String>()
+        });
+
+        Ok(Notebook {
+            text,
+            document,
+            indent,
+            source_spans,
+            edits: Vec::new(),
+            diffs: Vec::new(),
+            skipped_cells: Vec::new(),
+        })
+    }
+
+    /// The notebook's code cells that black can format, none at all if it isn't a python notebook
+    pub(crate) fn code_cells(&self, filepath: &Path) -> Vec<CodeCell> {
+        let language = self
+            .document
+            .pointer("/metadata/language_info/name")
+            .and_then(Value::as_str);
+
+        if language.is_some_and(|language| language != "python") {
+            return Vec::new();
+        }
+
+        self.cells()
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| cell.get("cell_type").and_then(Value::as_str) == Some("code"))
+            .filter_map(|(index, cell)| {
+                let original = cell_source(cell)?;
+                let label = PathBuf::from(format!("{}:cell_{}", filepath.display(), index));
+
+                CodeCell::new(index, original, label)
+            })
+            .collect()
+    }
+
+    /// Take in blackd's answer for one cell, a cell blackd couldn't parse is left as it was
+    pub(crate) fn record(
+        &mut self,
+        cell: &CodeCell,
+        result: Result<FormatOutcome<Vec<u8>>, BlackError>,
+    ) -> Result<(), BlackError> {
+        match result {
+            Ok(FormatOutcome::Unchanged) => {}
+            Ok(FormatOutcome::Changed(formatted)) => {
+                let formatted = cell.restore(&String::from_utf8_lossy(&formatted));
+
+                if formatted != cell.original {
+                    self.replace_source(cell.index, &formatted);
+                }
+            }
+            Ok(FormatOutcome::Diff(diff)) => {
+                self.diffs
+                    .push(cell.unmask(&relabel_diff(&diff, &cell.label)));
+            }
+            Err(err @ BlackError::Syntax { .. }) => self.skipped_cells.push((cell.index, err)),
+            Err(err) => return Err(err),
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn into_outcome(self) -> NotebookOutcome {
+        let outcome = if !self.diffs.is_empty() {
+            FormatOutcome::Diff(self.diffs.concat())
+        } else if !self.edits.is_empty() {
+            FormatOutcome::Changed(self.to_bytes())
+        } else {
+            FormatOutcome::Unchanged
+        };
+
+        NotebookOutcome {
+            outcome,
+            skipped_cells: self.skipped_cells,
+        }
+    }
+
+    fn cells(&self) -> &Vec<Value> {
+        self.document["cells"].as_array().unwrap()
+    }
+
+    /// Write a cell's new source in place of the old one, as a list of lines if that's how
+    /// it was, laid out the way nbformat does
+    fn replace_source(&mut self, index: usize, formatted: &str) {
+        let span = match &self.source_spans[index] {
+            Some(span) => span.clone(),
+            None => return,
+        };
+
+        let json = if !self.cells()[index]["source"].is_array() {
+            serde_json::to_string(formatted).unwrap()
+        } else {
+            let lines: Vec<&str> = formatted.split_inclusive('\n').collect();
+
+            match &self.indent {
+                Some(indent) if !lines.is_empty() => {
+                    // The lines go one level deeper than the `"source"` key
+                    let line_start = self.text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+                    let outer: String = self.text[line_start..]
+                        .chars()
+                        .take_while(|c| *c == ' ' || *c == '\t')
+                        .collect();
+                    let inner = format!("{}{}", outer, indent);
+                    let items: Vec<String> = lines
+                        .iter()
+                        .map(|line| format!("{}{}", inner, serde_json::to_string(line).unwrap()))
+                        .collect();
+
+                    format!("[\n{}\n{}]", items.join(",\n"), outer)
+                }
+                _ => serde_json::to_string(&lines).unwrap(),
+            }
+        };
+
+        self.edits.push((span, json));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = String::with_capacity(self.text.len());
+        let mut end = 0;
+
+        for (span, json) in &self.edits {
+            data.push_str(&self.text[end..span.start]);
+            data.push_str(json);
+            end = span.end;
+        }
+        data.push_str(&self.text[end..]);
+
+        data.into_bytes()
+    }
+}
+
+impl CodeCell {
+    fn new(index: usize, original: String, label: PathBuf) -> Option<CodeCell> {
+        if original.trim().is_empty() {
+            return None;
+        }
+
+        let (masked, masks) = mask_magics(&original)?;
+
+        // A trailing semicolon hides the cell's output in Jupyter, but black would drop it
+        let mut source = masked.trim_end_matches('\n').to_string();
+        let trailing_semicolon = source.ends_with(';');
+
+        if trailing_semicolon {
+            source.pop();
+        }
+
+        source.push('\n');
+
+        Some(CodeCell {
+            index,
+            source,
+            label,
+            original,
+            masks,
+            trailing_semicolon,
+        })
+    }
+
+    /// Put back everything that was taken out of the source before sending it to blackd
+    fn restore(&self, formatted: &str) -> String {
+        let mut restored = self.unmask(formatted.trim_end_matches('\n'));
+
+        if self.trailing_semicolon {
+            restored.push(';');
+        }
+
+        if self.original.ends_with('\n') {
+            restored.push('\n');
+        }
+
+        restored
+    }
+
+    fn unmask(&self, text: &str) -> String {
+        self.masks
+            .iter()
+            .fold(text.to_string(), |text, (token, magic)| {
+                text.replace(token.as_str(), magic)
+            })
+    }
+}
+
+/// A cell's source is either a single string or a list of lines
+fn cell_source(cell: &Value) -> Option<String> {
+    match cell.get("source")? {
+        Value::String(source) => Some(source.clone()),
+        Value::Array(lines) => lines
+            .iter()
+            .map(|line| line.as_str())
+            .collect::<Option<String>>(),
+        _ => None,
+    }
+}
+
+/// Swap IPython's magics, shell escapes and help queries (which black can't parse)
+/// for placeholder names, `None` for cells that aren't python at all
+fn mask_magics(source: &str) -> Option<(String, Vec<(String, String)>)> {
+    let mut masks: Vec<(String, String)> = Vec::new();
+    let mut masked = String::with_capacity(source.len());
+    let mut lines = source.split_inclusive('\n');
+    let mut scanner = Scanner::default();
+    let mut first = true;
+
+    while let Some(line) = lines.next() {
+        let content = line.trim_end_matches(&['\r', '\n'][..]);
+        let code = content.trim_start();
+
+        if first && code.starts_with("%%") {
+            let magic = code[2..].split_whitespace().next().unwrap_or("");
+
+            if !PYTHON_CELL_MAGICS.contains(&magic) {
+                return None;
+            }
+        }
+
+        first = false;
+
+        // Only a line that starts a statement can be a magic, anything else is
+        // the rest of a bracketed expression, a string or a continued line
+        let mut next = scanner.clone();
+        let comment = next.scan(code);
+        let statement = code[..comment.unwrap_or(code.len())].trim_end();
+
+        if !scanner.at_statement_start() || !is_magic(code, statement) {
+            scanner = next;
+            masked.push_str(line);
+            continue;
+        }
+
+        // A magic continues onto the next line after a backslash
+        let mut magic = code.to_string();
+        let mut ending = &line[content.len()..];
+
+        while magic.ends_with('\\') {
+            match lines.next() {
+                Some(next) => {
+                    let next_content = next.trim_end_matches(&['\r', '\n'][..]);
+                    magic.push_str(ending);
+                    magic.push_str(next_content);
+                    ending = &next[next_content.len()..];
+                }
+                None => break,
+            }
+        }
+
+        let token = (masks.len()..)
+            .map(|n| format!("__blackd_magic_{}__", n))
+            .find(|token| {
+                !source.contains(token.as_str()) && !masks.iter().any(|(used, _)| used == token)
+            })
+            .unwrap();
+
+        masked.push_str(&content[..content.len() - code.len()]);
+        masked.push_str(&token);
+        masked.push_str(ending);
+        masks.push((token, magic));
+    }
+
+    Some((masked, masks))
+}
+
+/// `statement` is the line's code without its comment
+fn is_magic(code: &str, statement: &str) -> bool {
+    code.starts_with('%')
+        || code.starts_with('!')
+        || code.starts_with('?')
+        || statement.ends_with('?')
+        || is_assigned_magic(code)
+}
+
+/// `files = !ls` and `timing = %timeit -o f()`
+fn is_assigned_magic(code: &str) -> bool {
+    match code.split_once('=') {
+        Some((target, value)) => {
+            let target = target.trim();
+
+            !target.is_empty()
+                && target
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == ',' || c == ' ')
+                && (value.trim_start().starts_with('!') || value.trim_start().starts_with('%'))
+        }
+        None => false,
+    }
+}
+
+/// Just enough of python's tokenizer to tell where statements start: it follows
+/// brackets, strings and backslash continuations from one line to the next
+#[derive(Debug, Clone, Default)]
+struct Scanner {
+    depth: usize,
+    triple_quote: Option<&'static [u8]>,
+    continued: bool,
+}
+
+impl Scanner {
+    fn at_statement_start(&self) -> bool {
+        self.depth == 0 && self.triple_quote.is_none() && !self.continued
+    }
+
+    /// Follow a line (without its line ending), handing back where its comment starts
+    fn scan(&mut self, line: &str) -> Option<usize> {
+        let bytes = line.as_bytes();
+        let mut idx = 0;
+
+        while idx < bytes.len() {
+            if let Some(quote) = self.triple_quote {
+                if bytes[idx] == b'\\' {
+                    idx += 2;
+                } else if bytes[idx..].starts_with(quote) {
+                    self.triple_quote = None;
+                    idx += quote.len();
+                } else {
+                    idx += 1;
+                }
+                continue;
+            }
+
+            match bytes[idx] {
+                b'#' => {
+                    self.continued = false;
+                    return Some(idx);
+                }
+                quote @ (b'"' | b'\'') => {
+                    let triple: &'static [u8] = if quote == b'"' { b"\"\"\"" } else { b"\'\'\'" };
+
+                    if bytes[idx..].starts_with(triple) {
+                        self.triple_quote = Some(triple);
+                        idx += triple.len();
+                        continue;
+                    }
+
+                    idx += 1;
+
+                    while idx < bytes.len() && bytes[idx] != quote {
+                        idx += if bytes[idx] == b'\\' { 2 } else { 1 };
+                    }
+                }
+                b'(' | b'[' | b'{' => self.depth += 1,
+                b')' | b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                _ => {}
+            }
+
+            idx += 1;
+        }
+
+        self.continued = self.triple_quote.is_none() && bytes.last() == Some(&b'\\');
+
+        None
+    }
+}
+
+/// Where each cell's `source` value is in a notebook's (valid) JSON text, so that it can
+/// be replaced without writing the rest of the notebook back out
+fn source_spans(text: &str) -> Option<Vec<Option<Range<usize>>>> {
+    let bytes = text.as_bytes();
+    let members = json_members(text, skip_whitespace(bytes, 0))?;
+    let cells = match members.iter().rev().find(|(key, _)| key == "cells") {
+        Some((_, cells)) => cells.start,
+        None => return Some(Vec::new()),
+    };
+
+    json_elements(bytes, cells)?
+        .into_iter()
+        .map(|cell| {
+            if bytes[cell.start] != b'{' {
+                return Some(None);
+            }
+
+            let members = json_members(text, cell.start)?;
+            // Like serde_json, the last of repeated keys wins
+            Some(
+                members
+                    .into_iter()
+                    .rev()
+                    .find(|(key, _)| key == "source")
+                    .map(|(_, span)| span),
+            )
+        })
+        .collect()
+}
+
+/// The keys and value spans of the object starting at `start`
+fn json_members(text: &str, start: usize) -> Option<Vec<(String, Range<usize>)>> {
+    let bytes = text.as_bytes();
+    let mut members = Vec::new();
+
+    if bytes.get(start) != Some(&b'{') {
+        return None;
+    }
+
+    let mut idx = skip_whitespace(bytes, start + 1);
+    if bytes.get(idx) == Some(&b'}') {
+        return Some(members);
+    }
+
+    loop {
+        let key_end = json_value_end(bytes, idx)?;
+        let key: String = serde_json::from_str(&text[idx..key_end]).ok()?;
+
+        idx = skip_whitespace(bytes, key_end);
+        if bytes.get(idx) != Some(&b':') {
+            return None;
+        }
+
+        let value_start = skip_whitespace(bytes, idx + 1);
+        let value_end = json_value_end(bytes, value_start)?;
+        members.push((key, value_start..value_end));
+
+        idx = skip_whitespace(bytes, value_end);
+        match bytes.get(idx)? {
+            b',' => idx = skip_whitespace(bytes, idx + 1),
+            b'}' => return Some(members),
+            _ => return None,
+        }
+    }
+}
+
+/// The spans of the elements of the array starting at `start`
+fn json_elements(bytes: &[u8], start: usize) -> Option<Vec<Range<usize>>> {
+    let mut elements = Vec::new();
+
+    if bytes.get(start) != Some(&b'[') {
+        return None;
+    }
+
+    let mut idx = skip_whitespace(bytes, start + 1);
+    if bytes.get(idx) == Some(&b']') {
+        return Some(elements);
+    }
+
+    loop {
+        let end = json_value_end(bytes, idx)?;
+        elements.push(idx..end);
+
+        idx = skip_whitespace(bytes, end);
+        match bytes.get(idx)? {
+            b',' => idx = skip_whitespace(bytes, idx + 1),
+            b']' => return Some(elements),
+            _ => return None,
+        }
+    }
+}
+
+/// Where the JSON value starting at `start` ends
+fn json_value_end(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes.get(start)? {
+        b'"' => {
+            let mut idx = start + 1;
+            loop {
+                match bytes.get(idx)? {
+                    b'\\' => idx += 2,
+                    b'"' => return Some(idx + 1),
+                    _ => idx += 1,
+                }
+            }
+        }
+        b'{' | b'[' => {
+            let mut depth = 0;
+            let mut idx = start;
+            loop {
+                match bytes.get(idx)? {
+                    b'"' => {
+                        idx = json_value_end(bytes, idx)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(idx + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                idx += 1;
+            }
+        }
+        // numbers, true, false and null
+        _ => {
+            let length = bytes[start..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace())
+                .unwrap_or(bytes.len() - start);
+            Some(start + length)
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], start: usize) -> usize {
+    start
+        + bytes[start.min(bytes.len())..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::line_ranges::LineRange;
+
+    fn cell(source: &str) -> CodeCell {
+        CodeCell::new(0, source.to_string(), PathBuf::from("test.ipynb:cell_0")).unwrap()
+    }
+
+    fn unmasked(source: &str) {
+        let (masked, masks) = mask_magics(source).unwrap();
+
+        assert_eq!(masked, source);
+        assert!(masks.is_empty(), "{:?}", masks);
+    }
+
+    #[test]
+    fn masks_magics_shell_escapes_and_help() {
+        let source = "%timeit f()\n!pip install x\nfiles = !ls\nf?\n?g\nx = 1\n";
+        let (masked, masks) = mask_magics(source).unwrap();
+
+        assert_eq!(
+            masked,
+            "__blackd_magic_0__\n__blackd_magic_1__\n__blackd_magic_2__\n\
+             __blackd_magic_3__\n__blackd_magic_4__\nx = 1\n"
+        );
+        assert_eq!(masks[2].1, "files = !ls");
+    }
+
+    #[test]
+    fn leaves_percent_formatting_in_brackets_alone() {
+        unmasked("msg = (\"hello %s\"\n       % name)\n");
+        unmasked("values = [\n    1,\n    !flag,\n]\n");
+    }
+
+    #[test]
+    fn leaves_comments_ending_in_a_question_mark_alone() {
+        unmasked("if x:  # why?\n    y = 1\n");
+        unmasked("# what now?\nx = 1\n");
+        unmasked("s = 'why?'  # because\n");
+    }
+
+    #[test]
+    fn leaves_strings_and_continued_lines_alone() {
+        unmasked("doc = \"\"\"\n%not a magic\nreally?\n\"\"\"\n");
+        unmasked("doc = '''it's (\n'''\nx = 1\n");
+        unmasked("total = a \\\n    % b\n");
+        unmasked("s = \"#(\" + t\nx = 1\n");
+    }
+
+    #[test]
+    fn masks_magics_after_closed_brackets_and_strings() {
+        let (masked, masks) =
+            mask_magics("x = f(\n    1,\n)\ns = \"\"\"\n\"\"\"\n%time g()\n").unwrap();
+
+        assert_eq!(
+            masked,
+            "x = f(\n    1,\n)\ns = \"\"\"\n\"\"\"\n__blackd_magic_0__\n"
+        );
+        assert_eq!(
+            masks,
+            vec![("__blackd_magic_0__".to_string(), "%time g()".to_string())]
+        );
+    }
+
+    #[test]
+    fn masks_continued_magics_whole() {
+        let (masked, masks) = mask_magics("!ls \\\n  -la\nx = 1\n").unwrap();
+
+        assert_eq!(masked, "__blackd_magic_0__\nx = 1\n");
+        assert_eq!(masks[0].1, "!ls \\\n  -la");
+    }
+
+    #[test]
+    fn skips_cells_in_other_languages() {
+        assert!(mask_magics("%%bash\necho 'hi'\n").is_none());
+        assert!(CodeCell::new(0, "%%html\n<b>hi</b>".to_string(), PathBuf::new()).is_none());
+
+        let (masked, _) = mask_magics("%%timeit\nx = 1\n").unwrap();
+        assert_eq!(masked, "__blackd_magic_0__\nx = 1\n");
+    }
+
+    #[test]
+    fn tokens_dont_clash_with_the_source() {
+        let (masked, masks) =
+            mask_magics("__blackd_magic_0__ = 1\n%time f()\n%time g()\n").unwrap();
+
+        assert_eq!(
+            masked,
+            "__blackd_magic_0__ = 1\n__blackd_magic_1__\n__blackd_magic_2__\n"
+        );
+        assert_eq!(masks.len(), 2);
+    }
+
+    #[test]
+    fn restores_magics_at_blacks_indentation() {
+        let cell = cell("for i in x:\n  %time f(i)\n  y = 'a'");
+
+        assert_eq!(
+            cell.source,
+            "for i in x:\n  __blackd_magic_0__\n  y = 'a'\n"
+        );
+        assert_eq!(
+            cell.restore("for i in x:\n    __blackd_magic_0__\n    y = \"a\"\n"),
+            "for i in x:\n    %time f(i)\n    y = \"a\""
+        );
+    }
+
+    #[test]
+    fn round_trips_a_trailing_semicolon() {
+        let cell = cell("plt.plot( x );");
+
+        assert_eq!(cell.source, "plt.plot( x )\n");
+        assert_eq!(cell.restore("plt.plot(x)\n"), "plt.plot(x);");
+    }
+
+    #[test]
+    fn refuses_line_ranges() {
+        let options = FormatOptions::new().line_ranges(vec![LineRange::new(1, 2).unwrap()]);
+
+        match cell_options(&options) {
+            Err(BlackError::Config { message }) => {
+                assert_eq!(message, "Cannot use --line-ranges with ipynb files")
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(cell_options(&FormatOptions::new().pyi(true)).is_ok());
+    }
+
+    #[test]
+    fn keeps_a_trailing_newline() {
+        assert_eq!(cell("x = 'a'\n").restore("x = \"a\"\n"), "x = \"a\"\n");
+    }
+
+    #[test]
+    fn rewrites_only_the_changed_sources() {
+        let source = "{\n \"cells\": [\n  {\n   \"cell_type\": \"code\",\n   \"metadata\": {},\n   \"outputs\": [],\n   \"source\": [\n    \"x = 'a'\\n\",\n    \"y = 1\"\n   ]\n  },\n  {\n   \"cell_type\": \"markdown\",\n   \"metadata\": {},\n   \"source\": \"'quoted'\"\n  }\n ],\n \"metadata\": {\n  \"ratio\": 1.50,\n  \"language_info\": {\n   \"name\": \"python\"\n  }\n },\n \"nbformat\": 4\n}\n";
+        let path = Path::new("test.ipynb");
+        let mut notebook = Notebook::parse(source.as_bytes(), path).unwrap();
+        let cells = notebook.code_cells(path);
+
+        assert_eq!(cells.len(), 1);
+        notebook
+            .record(
+                &cells[0],
+                Ok(FormatOutcome::Changed(b"x = \"a\"\ny = 1\n".to_vec())),
+            )
+            .unwrap();
+
+        let expected = source.replace("\"x = 'a'\\n\"", "\"x = \\\"a\\\"\\n\"");
+
+        match notebook.into_outcome().outcome {
+            FormatOutcome::Changed(formatted) => {
+                assert_eq!(String::from_utf8(formatted).unwrap(), expected)
+            }
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn splices_sources_into_the_original_text() {
+        let source = "{\n\t\"nbformat\": 4,\n\t\"metadata\": {\"title\": \"caf\\u00e9 [x]\", \"scale\": 1e2},\n\t\"cells\": [\n\t\t{\"cell_type\": \"markdown\", \"source\": \"{]\"},\n\t\t{\n\t\t\t\"source\": [\"x = 'a'\"],\n\t\t\t\"cell_type\": \"code\"\n\t\t}\n\t]\n}";
+        let path = Path::new("test.ipynb");
+        let mut notebook = Notebook::parse(source.as_bytes(), path).unwrap();
+        let cells = notebook.code_cells(path);
+
+        assert_eq!(cells[0].index, 1);
+        notebook
+            .record(
+                &cells[0],
+                Ok(FormatOutcome::Changed(b"x = \"a\"\ny = 1\n".to_vec())),
+            )
+            .unwrap();
+
+        let expected = source.replace(
+            "[\"x = 'a'\"]",
+            "[\n\t\t\t\t\"x = \\\"a\\\"\\n\",\n\t\t\t\t\"y = 1\"\n\t\t\t]",
+        );
+
+        match notebook.into_outcome().outcome {
+            FormatOutcome::Changed(formatted) => {
+                assert_eq!(String::from_utf8(formatted).unwrap(), expected)
+            }
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn locates_the_sources() {
+        let text = r#"{"cells": [{"source": "a"}, 3, {"cell_type": "raw"}, {"source": ["\"]", "b"]}], "x": null}"#;
+        let spans: Vec<Option<&str>> = source_spans(text)
+            .unwrap()
+            .into_iter()
+            .map(|span| span.map(|span| &text[span]))
+            .collect();
+
+        assert_eq!(
+            spans,
+            vec![Some("\"a\""), None, None, Some(r#"["\"]", "b"]"#)]
+        );
+    }
+
+    #[test]
+    fn keeps_a_notebook_on_one_line() {
+        let source = r#"{"cells":[{"cell_type":"code","source":"x = 'a'"}]}"#;
+        let path = Path::new("test.ipynb");
+        let mut notebook = Notebook::parse(source.as_bytes(), path).unwrap();
+        let cells = notebook.code_cells(path);
+
+        notebook
+            .record(
+                &cells[0],
+                Ok(FormatOutcome::Changed(b"x = \"a\"\n".to_vec())),
+            )
+            .unwrap();
+
+        assert_eq!(
+            notebook.into_outcome().outcome,
+            FormatOutcome::Changed(
+                br#"{"cells":[{"cell_type":"code","source":"x = \"a\""}]}"#.to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn skips_cells_blackd_cant_parse() {
+        let source = r#"{"cells":[{"cell_type":"code","source":"x = ("}]}"#;
+        let path = Path::new("test.ipynb");
+        let mut notebook = Notebook::parse(source.as_bytes(), path).unwrap();
+        let cells = notebook.code_cells(path);
+        let err = BlackError::Syntax {
+            path: cells[0].label.clone(),
+            message: "Cannot parse".to_string(),
+        };
+
+        notebook.record(&cells[0], Err(err)).unwrap();
+
+        let outcome = notebook.into_outcome();
+        assert_eq!(outcome.outcome, FormatOutcome::Unchanged);
+        assert_eq!(outcome.skipped_cells.len(), 1);
+    }
+
+    #[test]
+    fn leaves_other_languages_alone() {
+        let source = r#"{"cells":[{"cell_type":"code","source":"x = 1"}],"metadata":{"language_info":{"name":"julia"}}}"#;
+        let notebook = Notebook::parse(source.as_bytes(), Path::new("test.ipynb")).unwrap();
+
+        assert!(notebook.code_cells(Path::new("test.ipynb")).is_empty());
+    }
+}
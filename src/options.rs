@@ -0,0 +1,230 @@
+use crate::line_ranges::{normalize_line_ranges, LineRange};
+use crate::target_version::TargetVersion;
+use crate::version::Feature;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+pub const DEFAULT_LINE_LENGTH: u8 = 88;
+
+/// How blackd should format the code sent to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub(crate) line_length: Option<u8>,
+    pub(crate) target_versions: Vec<TargetVersion>,
+    pub(crate) pyi: bool,
+    pub(crate) skip_string_normalization: bool,
+    pub(crate) skip_magic_trailing_comma: bool,
+    pub(crate) preview: bool,
+    pub(crate) unstable: bool,
+    pub(crate) unstable_features: Vec<String>,
+    pub(crate) line_ranges: Vec<LineRange>,
+    pub(crate) skip_source_first_line: bool,
+    pub(crate) fast: bool,
+    pub(crate) diff: bool,
+}
+
+impl FormatOptions {
+    pub fn new() -> FormatOptions {
+        FormatOptions::default()
+    }
+
+    /// how many characters per line to allow [default: 88]
+    pub fn line_length(mut self, line_length: u8) -> Self {
+        self.line_length = Some(line_length);
+        self
+    }
+
+    /// python versions that should be supported by black's output [default: per-file auto-detection]
+    pub fn target_versions(mut self, target_versions: Vec<TargetVersion>) -> Self {
+        self.target_versions = target_versions;
+        self
+    }
+
+    /// format the code as a typing stub, regardless of its file extension
+    pub fn pyi(mut self, pyi: bool) -> Self {
+        self.pyi = pyi;
+        self
+    }
+
+    /// don't normalize string quotes or prefixes
+    pub fn skip_string_normalization(mut self, skip: bool) -> Self {
+        self.skip_string_normalization = skip;
+        self
+    }
+
+    /// don't use trailing commas as a reason to split lines
+    pub fn skip_magic_trailing_comma(mut self, skip: bool) -> Self {
+        self.skip_magic_trailing_comma = skip;
+        self
+    }
+
+    /// enable black's potentially disruptive style changes that may be added to black's main functionality in the next major release
+    pub fn preview(mut self, preview: bool) -> Self {
+        self.preview = preview;
+        self
+    }
+
+    /// enable every preview feature, including the unstable ones (implies preview)
+    pub fn unstable(mut self, unstable: bool) -> Self {
+        self.unstable = unstable;
+        self
+    }
+
+    /// enable one specific unstable preview feature by name, e.g. `hug_parens_with_braces_and_square_brackets`
+    pub fn enable_unstable_feature<S: Into<String>>(mut self, feature: S) -> Self {
+        self.unstable_features.push(feature.into());
+        self
+    }
+
+    /// only format these lines of the source (they're sorted and merged) [default: the whole source]
+    pub fn line_ranges(mut self, line_ranges: Vec<LineRange>) -> Self {
+        self.line_ranges = normalize_line_ranges(line_ranges);
+        self
+    }
+
+    /// leave the source's first line alone (e.g. a non-python shebang wrapper), blackd won't even parse it
+    pub fn skip_source_first_line(mut self, skip: bool) -> Self {
+        self.skip_source_first_line = skip;
+        self
+    }
+
+    /// skip blackd's temporary sanity checks
+    pub fn fast(mut self, fast: bool) -> Self {
+        self.fast = fast;
+        self
+    }
+
+    /// have blackd return a diff of its changes instead of the reformatted code
+    pub fn diff(mut self, diff: bool) -> Self {
+        self.diff = diff;
+        self
+    }
+
+    /// The features these options need that older blackd releases don't support
+    pub fn features(&self) -> Vec<Feature> {
+        let mut features = Vec::new();
+
+        if self.preview {
+            features.push(Feature::Preview);
+        }
+
+        if self.unstable || !self.unstable_features.is_empty() {
+            features.push(Feature::Unstable);
+        }
+
+        if !self.line_ranges.is_empty() {
+            features.push(Feature::LineRanges);
+        }
+
+        if self.skip_source_first_line {
+            features.push(Feature::SkipSourceFirstLine);
+        }
+
+        features
+    }
+}
+
+pub(crate) fn headers_from_options(options: &FormatOptions) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let line_length = options
+        .line_length
+        .unwrap_or(DEFAULT_LINE_LENGTH)
+        .to_string();
+
+    // X-Protocol-Version
+    headers.insert("X-Protocol-Version", HeaderValue::from_str("1").unwrap());
+
+    // X-Line-Length
+    headers.insert(
+        "X-Line-Length",
+        HeaderValue::from_str(line_length.as_str()).unwrap(),
+    );
+
+    // X-Skip-String-Normalization
+    if options.skip_string_normalization {
+        headers.insert(
+            "X-Skip-String-Normalization",
+            HeaderValue::from_str("true").unwrap(),
+        );
+    }
+
+    // X-Skip-Magic-Trailing-Comma
+    if options.skip_magic_trailing_comma {
+        headers.insert(
+            "X-Skip-Magic-Trailing-Comma",
+            HeaderValue::from_str("true").unwrap(),
+        );
+    }
+
+    // X-Skip-Source-First-Line
+    if options.skip_source_first_line {
+        headers.insert(
+            "X-Skip-Source-First-Line",
+            HeaderValue::from_str("true").unwrap(),
+        );
+    }
+
+    // X-Preview
+    if options.preview {
+        headers.insert("X-Preview", HeaderValue::from_str("true").unwrap());
+    }
+
+    // X-Unstable
+    if options.unstable {
+        headers.insert("X-Unstable", HeaderValue::from_str("true").unwrap());
+    }
+
+    // X-Enable-Unstable-Feature
+    if !options.unstable_features.is_empty() {
+        // Names that can't even go in a header aren't features black knows, so they're dropped rather than panicking
+        if let Ok(value) = HeaderValue::from_str(&options.unstable_features.join(",")) {
+            headers.insert("X-Enable-Unstable-Feature", value);
+        }
+    }
+
+    // X-Line-Ranges
+    if !options.line_ranges.is_empty() {
+        let line_ranges = options
+            .line_ranges
+            .iter()
+            .map(|range| range.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        headers.insert(
+            "X-Line-Ranges",
+            HeaderValue::from_str(&line_ranges).unwrap(),
+        );
+    }
+
+    // X-Fast-Or-Safe
+    if options.fast {
+        headers.insert("X-Fast-Or-Safe", HeaderValue::from_str("fast").unwrap());
+    } else {
+        headers.insert("X-Fast-Or-Safe", HeaderValue::from_str("safe").unwrap());
+    }
+
+    // X-Python-Variant
+    if options.pyi {
+        // blackd can't combine stub formatting with target versions, so stubs are sent as `pyi` alone
+        headers.insert("X-Python-Variant", HeaderValue::from_static("pyi"));
+    } else if !options.target_versions.is_empty() {
+        let target_version = options
+            .target_versions
+            .iter()
+            .map(|version| version.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+
+        headers.insert(
+            "X-Python-Variant",
+            HeaderValue::from_str(&target_version).unwrap(),
+        );
+    }
+
+    // X-Diff
+    if options.diff {
+        headers.insert("X-Diff", HeaderValue::from_str("true").unwrap());
+    }
+
+    headers
+}
@@ -0,0 +1,288 @@
+use crate::CliOptions;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where the effective value of an option came from, in order of precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    Cli,
+    Env(String),
+    Config(PathBuf),
+    Default,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Cli => write!(f, "command line"),
+            Origin::Env(var) => write!(f, "{} environment variable", var),
+            Origin::Config(path) => write!(f, "{}", path.display()),
+            Origin::Default => write!(f, "default"),
+        }
+    }
+}
+
+/// The origin of every option, in the order they're declared in `CliOptions`
+#[derive(Debug, Clone)]
+pub struct Origins(Vec<(&'static str, Origin)>);
+
+impl Origins {
+    /// Switches can't be turned off on the command line, so only set values count as given
+    pub fn from_cli(options: &CliOptions) -> Origins {
+        let given = [
+            ("host", options.host.is_some()),
+            ("port", options.port.is_some()),
+            ("socket", options.socket.is_some()),
+            ("url", options.url.is_some()),
+            ("ca_cert", options.ca_cert.is_some()),
+            ("client_cert", options.client_cert.is_some()),
+            ("client_key", options.client_key.is_some()),
+            ("header", !options.header.is_empty()),
+            ("spawn_blackd", options.spawn_blackd),
+            ("blackd_path", options.blackd_path.is_some()),
+            ("keep_blackd", options.keep_blackd),
+            ("version_check", options.version_check),
+            ("verbose", options.verbose),
+            ("config", options.config.is_some()),
+            ("show_config", options.show_config),
+            ("line_length", options.line_length.is_some()),
+            ("target_version", options.target_version.is_some()),
+            ("pyi", options.pyi),
+            (
+                "skip_string_normalization",
+                options.skip_string_normalization,
+            ),
+            (
+                "skip_magic_trailing_comma",
+                options.skip_magic_trailing_comma,
+            ),
+            ("skip_source_first_line", options.skip_source_first_line),
+            ("preview", options.preview),
+            ("unstable", options.unstable),
+            (
+                "enable_unstable_feature",
+                !options.enable_unstable_feature.is_empty(),
+            ),
+            ("line_ranges", !options.line_ranges.is_empty()),
+            ("changed_since", options.changed_since.is_some()),
+            ("fast", options.fast),
+            ("safe", options.safe),
+            ("check", options.check),
+            ("diff", options.diff),
+            ("color", options.color),
+            ("include", options.include.is_some()),
+            ("exclude", options.exclude.is_some()),
+            ("extend_exclude", options.extend_exclude.is_some()),
+            ("force_exclude", options.force_exclude.is_some()),
+            ("timeout", options.timeout.is_some()),
+            ("connect_timeout", options.connect_timeout.is_some()),
+            ("retries", options.retries.is_some()),
+            ("workers", options.workers.is_some()),
+            ("stdin_filename", options.stdin_filename.is_some()),
+            ("src", !options.src.is_empty()),
+        ];
+
+        Origins(
+            given
+                .iter()
+                .map(|(name, given)| {
+                    let origin = if *given { Origin::Cli } else { Origin::Default };
+                    (*name, origin)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn get(&self, name: &str) -> &Origin {
+        self.0
+            .iter()
+            .find(|(option, _)| *option == name)
+            .map(|(_, origin)| origin)
+            .unwrap_or(&Origin::Default)
+    }
+
+    /// Whether a source with higher precedence than the defaults already set the option
+    pub fn is_set(&self, name: &str) -> bool {
+        *self.get(name) != Origin::Default
+    }
+
+    pub fn set(&mut self, name: &str, origin: Origin) {
+        if let Some(entry) = self.0.iter_mut().find(|(option, _)| *option == name) {
+            entry.1 = origin;
+        }
+    }
+}
+
+/// The effective configuration, one `name = value (origin)` line per option
+pub fn show_config(options: &CliOptions, origins: &Origins) -> String {
+    origins
+        .0
+        .iter()
+        .map(|(name, origin)| format!("{} = {} ({})", name, option_value(options, name), origin))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn option_value(options: &CliOptions, name: &str) -> String {
+    let regex = |regex: &Option<regex::Regex>| regex.as_ref().map(|regex| regex.to_string());
+
+    let value = match name {
+        "host" => Some(options.host().to_string()),
+        "port" => Some(options.port().to_string()),
+        "socket" => options.socket.clone(),
+        "url" => options.url.clone(),
+        "ca_cert" => options.ca_cert.clone(),
+        "client_cert" => options.client_cert.clone(),
+        "client_key" => options.client_key.clone(),
+        // Header values are likely to be credentials
+        "header" => Some(
+            options
+                .header
+                .iter()
+                .map(|header| match header.split_once(':') {
+                    Some((name, _)) => format!("{}: ***", name.trim()),
+                    None => header.clone(),
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+        ),
+        "spawn_blackd" => Some(options.spawn_blackd.to_string()),
+        "blackd_path" => Some(options.blackd_path().to_string()),
+        "keep_blackd" => Some(options.keep_blackd.to_string()),
+        "version_check" => Some(options.version_check.to_string()),
+        "verbose" => Some(options.verbose.to_string()),
+        "config" => options.config.clone(),
+        "show_config" => Some(options.show_config.to_string()),
+        "line_length" => Some(
+            options
+                .line_length
+                .unwrap_or(blackd_client::DEFAULT_LINE_LENGTH)
+                .to_string(),
+        ),
+        "target_version" => options.target_version.as_ref().map(|versions| {
+            versions
+                .iter()
+                .map(|version| version.to_string())
+                .collect::<Vec<String>>()
+                .join(",")
+        }),
+        "pyi" => Some(options.pyi.to_string()),
+        "skip_string_normalization" => Some(options.skip_string_normalization.to_string()),
+        "skip_magic_trailing_comma" => Some(options.skip_magic_trailing_comma.to_string()),
+        "skip_source_first_line" => Some(options.skip_source_first_line.to_string()),
+        "preview" => Some(options.preview.to_string()),
+        "unstable" => Some(options.unstable.to_string()),
+        "enable_unstable_feature" => Some(options.enable_unstable_feature.join(",")),
+        "line_ranges" => Some(
+            options
+                .line_ranges
+                .iter()
+                .map(|range| range.to_string())
+                .collect::<Vec<String>>()
+                .join(","),
+        ),
+        "changed_since" => options.changed_since.clone(),
+        "fast" => Some(options.fast.to_string()),
+        "safe" => Some(options.safe.to_string()),
+        "check" => Some(options.check.to_string()),
+        "diff" => Some(options.diff.to_string()),
+        "color" => Some(options.color.to_string()),
+        "include" => regex(&options.include),
+        "exclude" => regex(&options.exclude),
+        "extend_exclude" => regex(&options.extend_exclude),
+        "force_exclude" => regex(&options.force_exclude),
+        "timeout" => options.timeout.map(timeout_value),
+        "connect_timeout" => options.connect_timeout.map(timeout_value),
+        "retries" => Some(options.retries.unwrap_or(0).to_string()),
+        "workers" => options.workers.map(|workers| workers.to_string()),
+        "stdin_filename" => options.stdin_filename.clone(),
+        "src" => Some(options.src.join(" ")),
+        _ => None,
+    };
+
+    value.unwrap_or_else(|| "<unset>".to_string())
+}
+
+fn timeout_value(timeout: Duration) -> String {
+    if timeout.is_zero() {
+        "none".to_string()
+    } else {
+        format!("{:?}", timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argh::FromArgs;
+
+    fn cli(args: &[&str]) -> CliOptions {
+        CliOptions::from_args(&["blackd-client"], args).unwrap()
+    }
+
+    #[test]
+    fn every_option_has_an_origin() {
+        let help = CliOptions::from_args(&["blackd-client"], &["--help"])
+            .unwrap_err()
+            .output;
+
+        // Option lines look like `  -l, --line-length  how many...`, wrapped descriptions don't
+        let option = regex::Regex::new(r"^\s+(?:-\w, )?--([a-z][a-z-]*)(?:\s|$)").unwrap();
+
+        let mut options: Vec<String> = help
+            .lines()
+            .filter_map(|line| option.captures(line))
+            .map(|found| found[1].replace('-', "_"))
+            .filter(|option| option != "help")
+            .collect();
+
+        // The sources are the one positional argument
+        options.push("src".to_string());
+
+        assert_eq!(Origins::from_cli(&CliOptions::default()).names(), options);
+    }
+
+    #[test]
+    fn only_given_options_come_from_the_command_line() {
+        let options = cli(&["--line-length", "100", "--preview", "-S", "src"]);
+        let origins = Origins::from_cli(&options);
+
+        for name in ["line_length", "preview", "skip_string_normalization", "src"] {
+            assert_eq!(*origins.get(name), Origin::Cli, "{}", name);
+        }
+
+        for name in ["host", "pyi", "fast", "safe", "retries"] {
+            assert_eq!(*origins.get(name), Origin::Default, "{}", name);
+        }
+    }
+
+    #[test]
+    fn shows_values_with_their_origins() {
+        let options = cli(&[
+            "--line-length",
+            "100",
+            "--header",
+            "Authorization: secret",
+            "src",
+        ]);
+        let shown = show_config(&options, &Origins::from_cli(&options));
+
+        assert!(
+            shown.contains("line_length = 100 (command line)"),
+            "{}",
+            shown
+        );
+        assert!(shown.contains("host = localhost (default)"), "{}", shown);
+        assert!(
+            shown.contains("header = Authorization: *** (command line)"),
+            "{}",
+            shown
+        );
+        assert!(!shown.contains("secret"), "{}", shown);
+    }
+}
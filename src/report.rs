@@ -0,0 +1,272 @@
+use blackd_client::BlackError;
+use colored::*;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Whether a source file was (or, in --check/--diff mode, would be) changed by black
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Changed {
+    No,
+    Yes,
+    /// the file would be changed, as described by the contained diff
+    Diff(String),
+}
+
+/// Tallies the outcome of every source file and maps it to black's exit codes
+#[derive(Debug, Default)]
+pub struct Report {
+    check: bool,
+    diff: bool,
+    change_count: u32,
+    same_count: u32,
+    failure_count: u32,
+    retried_count: u32,
+}
+
+impl Report {
+    pub fn new(check: bool, diff: bool) -> Report {
+        Report {
+            check,
+            diff,
+            ..Report::default()
+        }
+    }
+
+    pub fn done(&mut self, filepath: &Path, changed: Changed) {
+        if let Changed::Diff(diff) = &changed {
+            // The diff itself is the program's output, everything else is just commentary
+            print!("{}", diff);
+            let _ = io::stdout().flush();
+        }
+
+        match changed {
+            Changed::Yes | Changed::Diff(_) => {
+                if self.check || self.diff {
+                    eprintln!(
+                        "{} {}",
+                        "would reformat".yellow(),
+                        format!("{:?}", filepath).yellow()
+                    );
+                } else {
+                    eprintln!(
+                        "{} {}",
+                        "Successfully reformatted".green(),
+                        format!("{:?}", filepath).yellow()
+                    );
+                }
+                self.change_count += 1;
+            }
+            Changed::No => {
+                eprintln!(
+                    "{} {}",
+                    format!("{:?}", filepath).yellow(),
+                    "already well formatted, good job.".green()
+                );
+                self.same_count += 1;
+            }
+        }
+    }
+
+    pub fn failed(&mut self, err: &BlackError) {
+        eprintln!("{} {}", "error:".red(), err);
+        self.failure_count += 1;
+    }
+
+    /// Note how many files only got through to blackd after being retried
+    pub fn retried(&mut self, count: u32) {
+        self.retried_count = count;
+    }
+
+    /// black's exit codes: 123 if any source failed (whatever the reason),
+    /// 1 if --check found files that would change, 0 otherwise
+    pub fn return_code(&self) -> i32 {
+        if self.failure_count > 0 {
+            123
+        } else if self.check && self.change_count > 0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let mut results: String = if self.return_code() == 0 {
+            "\nAll done! ✨ 🍰 ✨\n".yellow().to_string()
+        } else {
+            "\nOh no! 💥 💔 💥\n".red().to_string()
+        };
+
+        let (reformatted, unchanged, failed) = if self.check || self.diff {
+            (
+                "would be reformatted",
+                "would be left unchanged",
+                "would fail to reformat",
+            )
+        } else {
+            ("reformatted", "left unchanged", "failed to reformat")
+        };
+
+        if self.change_count > 0 {
+            results += "\n• ".yellow().to_string().as_str();
+            results += pluralize(self.change_count, reformatted)
+                .green()
+                .to_string()
+                .as_str();
+        }
+
+        if self.same_count > 0 {
+            results += "\n• ".red().to_string().as_str();
+            results += format!("{}.", pluralize(self.same_count, unchanged))
+                .yellow()
+                .to_string()
+                .as_str();
+        }
+
+        if self.failure_count > 0 {
+            results += "\n• ".red().to_string().as_str();
+            results += format!("{}.", pluralize(self.failure_count, failed))
+                .red()
+                .to_string()
+                .as_str();
+        }
+
+        if self.retried_count > 0 {
+            results += "\n• ".yellow().to_string().as_str();
+            results += format!("{}.", pluralize(self.retried_count, "needed retrying"))
+                .yellow()
+                .to_string()
+                .as_str();
+        }
+
+        results += "\n";
+
+        results
+    }
+}
+
+/// Colorize a unified diff the same way black does
+pub fn color_diff(diff: &str) -> String {
+    diff.split_inclusive('\n')
+        .map(|line| {
+            let (text, newline) = match line.strip_suffix('\n') {
+                Some(text) => (text, "\n"),
+                None => (line, ""),
+            };
+
+            if text.starts_with("+++") || text.starts_with("---") {
+                format!("\x1b[1m{}\x1b[0m{}", text, newline)
+            } else if text.starts_with("@@") {
+                format!("\x1b[36m{}\x1b[0m{}", text, newline)
+            } else if text.starts_with('+') {
+                format!("\x1b[32m{}\x1b[0m{}", text, newline)
+            } else if text.starts_with('-') {
+                format!("\x1b[31m{}\x1b[0m{}", text, newline)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect()
+}
+
+fn pluralize(count: u32, outcome: &str) -> String {
+    if count == 1 {
+        format!("1 file {}", outcome)
+    } else {
+        format!("{} files {}", count, outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    fn report(check: bool, diff: bool, changed: &[Changed], failures: usize) -> Report {
+        let mut report = Report::new(check, diff);
+
+        for changed in changed {
+            report.done(Path::new("test.py"), changed.clone());
+        }
+
+        for _ in 0..failures {
+            report.failed(&BlackError::io(
+                "test.py",
+                io::Error::new(ErrorKind::NotFound, "No such file or directory"),
+            ));
+        }
+
+        report
+    }
+
+    #[test]
+    fn exits_with_0_when_nothing_failed() {
+        assert_eq!(report(false, false, &[], 0).return_code(), 0);
+        assert_eq!(
+            report(false, false, &[Changed::Yes, Changed::No], 0).return_code(),
+            0
+        );
+        assert_eq!(
+            report(false, true, &[Changed::Diff(String::new())], 0).return_code(),
+            0
+        );
+    }
+
+    #[test]
+    fn exits_with_1_when_check_finds_changes() {
+        assert_eq!(report(true, false, &[Changed::No], 0).return_code(), 0);
+        assert_eq!(
+            report(true, false, &[Changed::Yes, Changed::No], 0).return_code(),
+            1
+        );
+        assert_eq!(
+            report(true, true, &[Changed::Diff(String::new())], 0).return_code(),
+            1
+        );
+    }
+
+    #[test]
+    fn exits_with_123_when_anything_failed() {
+        // Whatever the failure, an unreadable file here is no different from a syntax error
+        assert_eq!(report(false, false, &[Changed::Yes], 1).return_code(), 123);
+        assert_eq!(report(true, false, &[Changed::Yes], 2).return_code(), 123);
+        assert_eq!(report(false, true, &[], 1).return_code(), 123);
+    }
+
+    #[test]
+    fn summarizes_what_happened() {
+        let summary = report(false, false, &[Changed::Yes, Changed::Yes, Changed::No], 1).summary();
+
+        assert!(summary.contains("Oh no!"), "{}", summary);
+        assert!(summary.contains("2 files reformatted"), "{}", summary);
+        assert!(summary.contains("1 file left unchanged."), "{}", summary);
+        assert!(
+            summary.contains("1 file failed to reformat."),
+            "{}",
+            summary
+        );
+    }
+
+    #[test]
+    fn summarizes_what_would_happen() {
+        let mut check = report(true, false, &[Changed::Yes], 0);
+        check.retried(2);
+        let summary = check.summary();
+
+        assert!(summary.contains("Oh no!"), "{}", summary);
+        assert!(
+            summary.contains("1 file would be reformatted"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("2 files needed retrying."), "{}", summary);
+
+        let summary = report(false, true, &[Changed::No], 0).summary();
+
+        assert!(summary.contains("All done!"), "{}", summary);
+        assert!(
+            summary.contains("1 file would be left unchanged."),
+            "{}",
+            summary
+        );
+    }
+}
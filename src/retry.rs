@@ -0,0 +1,160 @@
+use blackd_client::BlackError;
+use colored::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait before the first retry, doubling with every retry after it
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Retries requests to blackd that failed for a transient reason (it's unreachable or
+/// had a server error), keeping track of the files that needed it
+#[derive(Debug, Default)]
+pub struct Retrier {
+    retries: u32,
+    retried: AtomicU32,
+}
+
+impl Retrier {
+    pub fn new(retries: u32) -> Retrier {
+        Retrier {
+            retries,
+            ..Retrier::default()
+        }
+    }
+
+    /// Make the request, retrying it with exponential backoff until it succeeds,
+    /// fails for good (e.g. with a syntax error) or runs out of retries
+    pub fn run<T, F>(&self, filepath: &Path, mut request: F) -> Result<T, BlackError>
+    where
+        F: FnMut() -> Result<T, BlackError>,
+    {
+        let mut attempt: u32 = 0;
+
+        loop {
+            match request() {
+                Err(err) if attempt < self.retries && err.is_transient() => {
+                    let delay = INITIAL_BACKOFF
+                        .checked_mul(2u32.saturating_pow(attempt))
+                        .unwrap_or(MAX_BACKOFF)
+                        .min(MAX_BACKOFF);
+
+                    if attempt == 0 {
+                        self.retried.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    attempt += 1;
+
+                    eprintln!(
+                        "{} {}, retrying {:?} in {:.1}s ({}/{})",
+                        "warning:".yellow(),
+                        err,
+                        filepath,
+                        delay.as_secs_f64(),
+                        attempt,
+                        self.retries
+                    );
+
+                    thread::sleep(delay);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// How many files needed at least one retry
+    pub fn retried_count(&self) -> u32 {
+        self.retried.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use std::cell::Cell;
+    use std::path::PathBuf;
+
+    fn internal() -> BlackError {
+        BlackError::Internal {
+            path: PathBuf::from("test.py"),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+            message: String::new(),
+        }
+    }
+
+    /// Nothing listens on port 1, so this is as real a connection failure as it gets
+    fn connection() -> BlackError {
+        let err = reqwest::blocking::get("http://127.0.0.1:1/").unwrap_err();
+
+        BlackError::from(err)
+    }
+
+    /// Run a request that fails with `err()` the first `failures` times, returning the attempts made
+    fn attempts(retrier: &Retrier, failures: u32, err: fn() -> BlackError) -> (u32, bool) {
+        let attempts = Cell::new(0);
+
+        let result = retrier.run(Path::new("test.py"), || {
+            attempts.set(attempts.get() + 1);
+
+            if attempts.get() <= failures {
+                Err(err())
+            } else {
+                Ok(())
+            }
+        });
+
+        (attempts.get(), result.is_ok())
+    }
+
+    #[test]
+    fn never_retries_what_would_fail_again() {
+        let retrier = Retrier::new(3);
+
+        let syntax = || BlackError::Syntax {
+            path: PathBuf::from("test.py"),
+            message: String::new(),
+        };
+        let rejected = || BlackError::RejectedOption {
+            message: String::new(),
+        };
+
+        assert_eq!(attempts(&retrier, 1, syntax), (1, false));
+        assert_eq!(attempts(&retrier, 1, rejected), (1, false));
+        assert_eq!(retrier.retried_count(), 0);
+    }
+
+    #[test]
+    fn retries_transient_failures_up_to_the_limit() {
+        assert!(connection().is_transient());
+
+        let retrier = Retrier::new(1);
+
+        assert_eq!(attempts(&retrier, 1, connection), (2, true));
+        assert_eq!(attempts(&retrier, 2, internal), (2, false));
+    }
+
+    #[test]
+    fn doesnt_retry_without_retries() {
+        let retrier = Retrier::new(0);
+
+        assert_eq!(attempts(&retrier, 1, internal), (1, false));
+        assert_eq!(retrier.retried_count(), 0);
+    }
+
+    #[test]
+    fn counts_each_file_once() {
+        let retrier = Retrier::new(2);
+
+        assert_eq!(attempts(&retrier, 2, internal), (3, true));
+        assert_eq!(retrier.retried_count(), 1);
+
+        assert_eq!(attempts(&retrier, 0, internal), (1, true));
+        assert_eq!(retrier.retried_count(), 1);
+
+        assert_eq!(attempts(&retrier, 1, internal), (2, true));
+        assert_eq!(retrier.retried_count(), 2);
+    }
+}
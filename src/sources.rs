@@ -0,0 +1,470 @@
+use blackd_client::is_notebook;
+use ignore::{DirEntry, WalkBuilder};
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// black's default `--include` pattern
+pub const DEFAULT_INCLUDES: &str = r"(\.pyi?|\.ipynb)$";
+
+/// black's default `--exclude` pattern
+pub const DEFAULT_EXCLUDES: &str = r"/(\.direnv|\.eggs|\.git|\.hg|\.ipynb_checkpoints|\.mypy_cache|\.nox|\.pytest_cache|\.ruff_cache|\.tox|\.svn|\.venv|\.vscode|__pypackages__|_build|buck-out|build|dist|venv)/";
+
+pub fn parse_regex(pattern: &str) -> Result<Regex, String> {
+    // Multi-line patterns are treated as verbose, the same way black treats them
+    let pattern = if pattern.contains('\n') {
+        format!("(?x){}", pattern)
+    } else {
+        pattern.to_string()
+    };
+
+    Regex::new(pattern.as_str())
+        .map_err(|err| format!("Invalid regular expression {:?}: {}", pattern, err))
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFilter {
+    root: PathBuf,
+    include: Regex,
+    exclude: Regex,
+    extend_exclude: Option<Regex>,
+    force_exclude: Option<Regex>,
+    use_gitignore: bool,
+}
+
+impl SourceFilter {
+    pub fn new(
+        root: PathBuf,
+        include: Option<Regex>,
+        exclude: Option<Regex>,
+        extend_exclude: Option<Regex>,
+        force_exclude: Option<Regex>,
+    ) -> SourceFilter {
+        // Like black, .gitignore is only honored when --exclude isn't explicitly given
+        let use_gitignore = exclude.is_none();
+
+        SourceFilter {
+            root,
+            include: include.unwrap_or_else(|| Regex::new(DEFAULT_INCLUDES).unwrap()),
+            exclude: exclude.unwrap_or_else(|| Regex::new(DEFAULT_EXCLUDES).unwrap()),
+            extend_exclude,
+            force_exclude,
+            use_gitignore,
+        }
+    }
+
+    /// The path as black's regexes see it: relative to the project root,
+    /// `/`-separated, with a leading `/` and a trailing `/` for directories
+    fn normalize(&self, path: &Path, is_dir: bool) -> String {
+        let absolute = absolute_path(path);
+        let relative = absolute.strip_prefix(&self.root).unwrap_or(&absolute);
+
+        let mut normalized = String::from("/");
+
+        normalized += relative
+            .components()
+            .map(|part| part.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .as_str();
+
+        if is_dir && !normalized.ends_with('/') {
+            normalized.push('/');
+        }
+
+        normalized
+    }
+
+    fn is_excluded(&self, normalized: &str) -> bool {
+        self.exclude.is_match(normalized)
+            || self
+                .extend_exclude
+                .as_ref()
+                .map(|regex| regex.is_match(normalized))
+                .unwrap_or(false)
+            || self.is_force_excluded(normalized)
+    }
+
+    fn is_force_excluded(&self, normalized: &str) -> bool {
+        self.force_exclude
+            .as_ref()
+            .map(|regex| regex.is_match(normalized))
+            .unwrap_or(false)
+    }
+
+    fn accepts_entry(&self, entry: &DirEntry) -> bool {
+        // The directory being walked was named explicitly, so only descendants are filtered
+        if entry.depth() == 0 {
+            return true;
+        }
+
+        let is_dir = entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
+        let normalized = self.normalize(entry.path(), is_dir);
+
+        if self.is_excluded(normalized.as_str()) {
+            return false;
+        }
+
+        is_dir || self.include.is_match(normalized.as_str())
+    }
+
+    /// Whether an explicitly named source file should be formatted
+    pub fn accepts_explicit(&self, path: &Path) -> bool {
+        !self.is_force_excluded(self.normalize(path, false).as_str())
+    }
+}
+
+/// Line numbers only mean something for one particular python file, not for several
+/// files nor for a notebook's JSON
+pub fn check_line_ranges(sources: &[PathBuf], stdin_filename: Option<&Path>) -> Result<(), String> {
+    if sources.len() > 1 {
+        return Err("--line-ranges can't be used with multiple source files".to_string());
+    }
+
+    let is_notebook_source = |source_file: &PathBuf| match stdin_filename {
+        Some(name) if source_file.as_os_str() == "-" => is_notebook(name),
+        _ => is_notebook(source_file),
+    };
+
+    if sources.iter().any(is_notebook_source) {
+        return Err("Cannot use --line-ranges with ipynb files".to_string());
+    }
+
+    Ok(())
+}
+
+/// Split off the notebooks, whose changed lines are lines of their JSON that can't be
+/// told apart by cell
+pub fn split_notebooks(sources: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    sources
+        .into_iter()
+        .partition(|source_file| !is_notebook(source_file))
+}
+
+pub fn collect_sources<T: AsRef<str>>(
+    src: &[T],
+    stdin_filename: Option<&Path>,
+    filter: &SourceFilter,
+) -> Vec<PathBuf> {
+    let mut sources: Vec<PathBuf> = Vec::new();
+    let mut seen: BTreeSet<PathBuf> = BTreeSet::new();
+
+    for entry in src.iter() {
+        let path = PathBuf::from(entry.as_ref());
+
+        if entry.as_ref() == "-" {
+            // stdin is subject to --force-exclude under the name it's been given, if any
+            let accepted = stdin_filename
+                .map(|name| filter.accepts_explicit(name))
+                .unwrap_or(true);
+
+            if accepted && seen.insert(path.clone()) {
+                sources.push(path);
+            }
+        } else if path.is_dir() {
+            // Directories are walked recursively, in a stable order
+            for source_file in walk_directory(path.as_path(), filter) {
+                if seen.insert(source_file.clone()) {
+                    sources.push(source_file);
+                }
+            }
+        } else if filter.accepts_explicit(path.as_path()) && seen.insert(path.clone()) {
+            // Explicitly named files are passed along whatever their extension, unless force-excluded
+            sources.push(path);
+        }
+    }
+
+    sources
+}
+
+fn walk_directory(directory: &Path, filter: &SourceFilter) -> Vec<PathBuf> {
+    let entry_filter = Arc::new(filter.clone());
+
+    let walker = WalkBuilder::new(directory)
+        .standard_filters(false)
+        .git_ignore(filter.use_gitignore)
+        .parents(filter.use_gitignore)
+        .require_git(false)
+        .follow_links(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| entry_filter.accepts_entry(entry))
+        .build();
+
+    let mut found: Vec<PathBuf> = Vec::new();
+
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+                    found.push(entry.into_path());
+                }
+            }
+            Err(err) => eprintln!("Could not read {:?}: {}", directory, err),
+        }
+    }
+
+    found
+}
+
+/// Find the directory containing `.git`, `.hg` or `pyproject.toml`
+/// that is the closest common ancestor of all of the supplied sources
+pub fn find_project_root<T: AsRef<str>>(src: &[T]) -> PathBuf {
+    let cwd = env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+
+    let src_parents: Vec<Vec<PathBuf>> = src
+        .iter()
+        .map(|entry| {
+            let path = absolute_path(Path::new(entry.as_ref()));
+            let mut parents: Vec<PathBuf> = path.ancestors().skip(1).map(PathBuf::from).collect();
+
+            if path.is_dir() {
+                parents.push(path);
+            }
+
+            parents
+        })
+        .collect();
+
+    // The deepest directory that every source lives under
+    let common_base = match src_parents.split_first() {
+        Some((first, rest)) => first
+            .iter()
+            .filter(|candidate| rest.iter().all(|parents| parents.contains(candidate)))
+            .max_by_key(|candidate| candidate.components().count())
+            .cloned()
+            .unwrap_or_else(|| cwd.clone()),
+        None => cwd.clone(),
+    };
+
+    for directory in common_base.ancestors() {
+        if directory.join(".git").exists()
+            || directory.join(".hg").is_dir()
+            || directory.join("pyproject.toml").is_file()
+        {
+            return directory.to_path_buf();
+        }
+    }
+
+    common_base
+        .ancestors()
+        .last()
+        .map(PathBuf::from)
+        .unwrap_or(common_base)
+}
+
+fn absolute_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| {
+        env::current_dir()
+            .map(|cwd| cwd.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// A project with sources, stubs, notebooks, junk, black's default excludes and a .gitignore
+    fn project() -> (TempDir, PathBuf) {
+        let directory = tempfile::tempdir().unwrap();
+        let root = directory.path().canonicalize().unwrap();
+
+        for file in [
+            "app.py",
+            "app.pyi",
+            "notes.ipynb",
+            "readme.txt",
+            "ignored.py",
+            "pkg/mod.py",
+            "pkg/skip/gen.py",
+            "sub/pkg/deep.py",
+            "build/out.py",
+            ".venv/lib.py",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"x = 1\n").unwrap();
+        }
+
+        fs::write(root.join(".gitignore"), b"ignored.py\n").unwrap();
+
+        (directory, root)
+    }
+
+    fn filter(root: &Path, exclude: Option<&str>, force_exclude: Option<&str>) -> SourceFilter {
+        SourceFilter::new(
+            root.to_path_buf(),
+            None,
+            exclude.map(|regex| Regex::new(regex).unwrap()),
+            None,
+            force_exclude.map(|regex| Regex::new(regex).unwrap()),
+        )
+    }
+
+    /// The collected sources, relative to the project root
+    fn collect(root: &Path, src: &[PathBuf], filter: &SourceFilter) -> Vec<String> {
+        let src: Vec<String> = src
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+
+        collect_sources(&src, Some(Path::new("stdin.py")), filter)
+            .iter()
+            .map(|path| {
+                path.strip_prefix(root)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_paths_relative_to_the_root() {
+        let (_directory, root) = project();
+        let filter = filter(&root, None, None);
+
+        assert_eq!(
+            filter.normalize(&root.join("pkg/mod.py"), false),
+            "/pkg/mod.py"
+        );
+        assert_eq!(filter.normalize(&root.join("pkg"), true), "/pkg/");
+        assert_eq!(filter.normalize(&root, true), "/");
+    }
+
+    #[test]
+    fn walks_with_the_default_filters() {
+        let (_directory, root) = project();
+
+        assert_eq!(
+            collect(
+                &root,
+                std::slice::from_ref(&root),
+                &filter(&root, None, None)
+            ),
+            vec![
+                "app.py",
+                "app.pyi",
+                "notes.ipynb",
+                "pkg/mod.py",
+                "pkg/skip/gen.py",
+                "sub/pkg/deep.py"
+            ]
+        );
+    }
+
+    #[test]
+    fn an_explicit_exclude_replaces_the_defaults_and_the_gitignore() {
+        let (_directory, root) = project();
+
+        assert_eq!(
+            collect(
+                &root,
+                std::slice::from_ref(&root),
+                &filter(&root, Some("^/pkg/"), None)
+            ),
+            vec![
+                ".venv/lib.py",
+                "app.py",
+                "app.pyi",
+                "build/out.py",
+                "ignored.py",
+                "notes.ipynb",
+                "sub/pkg/deep.py"
+            ]
+        );
+    }
+
+    #[test]
+    fn directory_patterns_match_the_trailing_slash() {
+        let (_directory, root) = project();
+        let sources = collect(
+            &root,
+            std::slice::from_ref(&root),
+            &filter(&root, Some("/skip/"), None),
+        );
+
+        assert!(!sources.contains(&"pkg/skip/gen.py".to_string()));
+        assert!(sources.contains(&"pkg/mod.py".to_string()));
+    }
+
+    #[test]
+    fn explicit_files_skip_include_and_exclude() {
+        let (_directory, root) = project();
+        let src = [root.join("readme.txt"), root.join("build/out.py")];
+
+        assert_eq!(
+            collect(&root, &src, &filter(&root, None, None)),
+            vec!["readme.txt", "build/out.py"]
+        );
+    }
+
+    #[test]
+    fn force_exclude_applies_to_explicit_files() {
+        let (_directory, root) = project();
+        let src = [root.join("app.py"), root.join("build/out.py")];
+
+        assert_eq!(
+            collect(&root, &src, &filter(&root, None, Some("/build/"))),
+            vec!["app.py"]
+        );
+    }
+
+    #[test]
+    fn force_exclude_applies_to_stdin_by_its_filename() {
+        let (_directory, root) = project();
+        let src = [PathBuf::from("-")];
+
+        assert_eq!(
+            collect(&root, &src, &filter(&root, None, Some(r"stdin\.py$"))),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            collect(&root, &src, &filter(&root, None, Some(r"other\.py$"))),
+            vec!["-"]
+        );
+    }
+
+    #[test]
+    fn line_ranges_need_a_single_python_file() {
+        let python = vec![PathBuf::from("app.py")];
+        let notebook = vec![PathBuf::from("notes.ipynb")];
+        let stdin = vec![PathBuf::from("-")];
+
+        assert_eq!(check_line_ranges(&python, None), Ok(()));
+        assert_eq!(check_line_ranges(&stdin, None), Ok(()));
+        assert_eq!(
+            check_line_ranges(&[python[0].clone(), python[0].clone()], None),
+            Err("--line-ranges can't be used with multiple source files".to_string())
+        );
+        assert_eq!(
+            check_line_ranges(&notebook, None),
+            Err("Cannot use --line-ranges with ipynb files".to_string())
+        );
+        assert_eq!(
+            check_line_ranges(&stdin, Some(Path::new("notes.IPYNB"))),
+            Err("Cannot use --line-ranges with ipynb files".to_string())
+        );
+    }
+
+    #[test]
+    fn splits_off_the_notebooks() {
+        let sources = vec![
+            PathBuf::from("app.py"),
+            PathBuf::from("notes.ipynb"),
+            PathBuf::from("app.pyi"),
+        ];
+
+        assert_eq!(
+            split_notebooks(sources),
+            (
+                vec![PathBuf::from("app.py"), PathBuf::from("app.pyi")],
+                vec![PathBuf::from("notes.ipynb")]
+            )
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The python versions black knows how to target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TargetVersion {
+    PY33,
+    PY34,
+    PY35,
+    PY36,
+    PY37,
+    PY38,
+    PY39,
+    PY310,
+    PY311,
+    PY312,
+    PY313,
+    PY314,
+}
+
+impl TargetVersion {
+    pub const ALL: [TargetVersion; 12] = [
+        TargetVersion::PY33,
+        TargetVersion::PY34,
+        TargetVersion::PY35,
+        TargetVersion::PY36,
+        TargetVersion::PY37,
+        TargetVersion::PY38,
+        TargetVersion::PY39,
+        TargetVersion::PY310,
+        TargetVersion::PY311,
+        TargetVersion::PY312,
+        TargetVersion::PY313,
+        TargetVersion::PY314,
+    ];
+
+    /// The (major, minor) python version being targeted
+    pub fn version(&self) -> (u8, u8) {
+        match self {
+            TargetVersion::PY33 => (3, 3),
+            TargetVersion::PY34 => (3, 4),
+            TargetVersion::PY35 => (3, 5),
+            TargetVersion::PY36 => (3, 6),
+            TargetVersion::PY37 => (3, 7),
+            TargetVersion::PY38 => (3, 8),
+            TargetVersion::PY39 => (3, 9),
+            TargetVersion::PY310 => (3, 10),
+            TargetVersion::PY311 => (3, 11),
+            TargetVersion::PY312 => (3, 12),
+            TargetVersion::PY313 => (3, 13),
+            TargetVersion::PY314 => (3, 14),
+        }
+    }
+}
+
+impl fmt::Display for TargetVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (major, minor) = self.version();
+        write!(f, "py{}{}", major, minor)
+    }
+}
+
+impl FromStr for TargetVersion {
+    type Err = String;
+
+    /// Accepts black's own spelling (`py311`) as well as dotted versions (`3.11`, `py3.11`)
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let normalized = value.trim().to_ascii_lowercase();
+        let digits = normalized.strip_prefix("py").unwrap_or(normalized.as_str());
+
+        let (major, minor) = match digits.split_once('.') {
+            Some((major, minor)) => (major, minor),
+            None if digits.len() > 1 && digits.is_char_boundary(1) => digits.split_at(1),
+            None => ("", ""),
+        };
+
+        let version = (major.parse::<u8>(), minor.parse::<u8>());
+
+        TargetVersion::ALL
+            .iter()
+            .find(|target| match version {
+                (Ok(major), Ok(minor)) => target.version() == (major, minor),
+                _ => false,
+            })
+            .copied()
+            .ok_or_else(|| {
+                // Every blackd since black 22.1 would reject it anyway
+                let hint = if normalized == "pyi" {
+                    " (use --pyi to format stub files)".to_string()
+                } else if major == "2" {
+                    ": Python 2 is not supported".to_string()
+                } else {
+                    format!(
+                        " (expected e.g. py311 or 3.11, supported versions are: {})",
+                        TargetVersion::ALL
+                            .iter()
+                            .map(|target| target.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )
+                };
+
+                format!("unknown target version {:?}{}", value.trim(), hint)
+            })
+    }
+}
+
+pub fn parse_py_versions(version_string: &str) -> Result<Vec<TargetVersion>, String> {
+    let mut versions = version_string
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(TargetVersion::from_str)
+        .collect::<Result<Vec<TargetVersion>, String>>()?;
+
+    versions.sort();
+    versions.dedup();
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_black_and_dotted_spellings() {
+        for value in ["py311", "3.11", "py3.11", " PY311 "] {
+            assert_eq!(value.parse::<TargetVersion>(), Ok(TargetVersion::PY311));
+        }
+
+        assert_eq!("py39".parse::<TargetVersion>(), Ok(TargetVersion::PY39));
+        assert_eq!("3.9".parse::<TargetVersion>(), Ok(TargetVersion::PY39));
+    }
+
+    #[test]
+    fn rejects_pyi_with_a_hint() {
+        let err = "pyi".parse::<TargetVersion>().unwrap_err();
+
+        assert!(err.contains("--pyi"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_python_2() {
+        for value in ["py27", "2.7"] {
+            let err = value.parse::<TargetVersion>().unwrap_err();
+
+            assert!(err.contains("Python 2 is not supported"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for value in ["", "py", "3", "py3.", "3.x", "py399", "python3.11"] {
+            let err = value.parse::<TargetVersion>().unwrap_err();
+
+            assert!(err.contains("supported versions are"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn parses_sorted_deduplicated_lists() {
+        assert_eq!(
+            parse_py_versions("py311, 3.8,,py3.11"),
+            Ok(vec![TargetVersion::PY38, TargetVersion::PY311])
+        );
+        assert!(parse_py_versions("py311,py27").is_err());
+    }
+}
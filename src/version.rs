@@ -0,0 +1,133 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The black release behind a blackd server, as reported in its `X-Black-Version` header
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlackVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl BlackVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> BlackVersion {
+        BlackVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        *self >= feature.since()
+    }
+}
+
+impl fmt::Display for BlackVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for BlackVersion {
+    type Err = String;
+
+    /// Pre-releases and dev builds (`23.1a1`, `24.1.1.dev3+g1234`) count as their release
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.trim().split('.').map(|part| {
+            let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+            digits.parse::<u32>().ok()
+        });
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Some(major)), Some(Some(minor)), patch) => Ok(BlackVersion::new(
+                major,
+                minor,
+                patch.flatten().unwrap_or(0),
+            )),
+            _ => Err(format!("unrecognized black version {:?}", value.trim())),
+        }
+    }
+}
+
+/// Request headers that only newer blackd releases understand, older releases silently ignore them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// `X-Preview`
+    Preview,
+    /// `X-Skip-Source-First-Line`
+    SkipSourceFirstLine,
+    /// `X-Line-Ranges`
+    LineRanges,
+    /// `X-Unstable` and `X-Enable-Unstable-Feature`
+    Unstable,
+}
+
+impl Feature {
+    /// The first black release whose blackd understands the feature
+    pub fn since(&self) -> BlackVersion {
+        match self {
+            Feature::Preview => BlackVersion::new(22, 1, 0),
+            Feature::SkipSourceFirstLine => BlackVersion::new(22, 12, 0),
+            Feature::LineRanges => BlackVersion::new(23, 11, 0),
+            Feature::Unstable => BlackVersion::new(24, 1, 0),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::Preview => write!(f, "preview style"),
+            Feature::SkipSourceFirstLine => write!(f, "skipping the source's first line"),
+            Feature::LineRanges => write!(f, "line ranges"),
+            Feature::Unstable => write!(f, "unstable style features"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_releases() {
+        assert_eq!("24.2.0".parse(), Ok(BlackVersion::new(24, 2, 0)));
+        assert_eq!(" 22.12.1\n".parse(), Ok(BlackVersion::new(22, 12, 1)));
+        assert_eq!("23.1".parse(), Ok(BlackVersion::new(23, 1, 0)));
+    }
+
+    #[test]
+    fn parses_pre_releases_and_dev_builds_as_their_release() {
+        assert_eq!("23.1a1".parse(), Ok(BlackVersion::new(23, 1, 0)));
+        assert_eq!("22.1.0rc2".parse(), Ok(BlackVersion::new(22, 1, 0)));
+        assert_eq!("24.1.1.dev3+g1234".parse(), Ok(BlackVersion::new(24, 1, 1)));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for value in ["", "24", "black", "v24.1.0", ".1.0"] {
+            assert!(value.parse::<BlackVersion>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn supports_features_from_their_first_release() {
+        let version = BlackVersion::new(23, 11, 0);
+
+        assert!(version.supports(Feature::Preview));
+        assert!(version.supports(Feature::SkipSourceFirstLine));
+        assert!(version.supports(Feature::LineRanges));
+        assert!(!version.supports(Feature::Unstable));
+
+        assert!(!BlackVersion::new(23, 10, 1).supports(Feature::LineRanges));
+        assert!(!BlackVersion::new(21, 12, 0).supports(Feature::Preview));
+        assert!(BlackVersion::new(24, 1, 0).supports(Feature::Unstable));
+    }
+
+    #[test]
+    fn orders_versions_numerically() {
+        assert!(BlackVersion::new(22, 12, 0) > BlackVersion::new(22, 9, 0));
+        assert!(BlackVersion::new(24, 1, 0) > BlackVersion::new(23, 12, 1));
+    }
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Run `job` over every item using at most `workers` threads,
+/// returning the results in the same order as the items
+pub fn run_in_pool<T, R, F>(items: &[T], workers: usize, job: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let workers = workers.max(1).min(items.len());
+
+    // No point in spinning up threads for a single worker (or no work at all)
+    if workers <= 1 {
+        return items.iter().map(job).collect();
+    }
+
+    let next_item = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<(usize, R)>();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next_item, job) = (&next_item, &job);
+
+            scope.spawn(move || loop {
+                let idx = next_item.fetch_add(1, Ordering::SeqCst);
+
+                match items.get(idx) {
+                    Some(item) => {
+                        if sender.send((idx, job(item))).is_err() {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            });
+        }
+    });
+
+    drop(sender);
+
+    let mut results: Vec<Option<R>> = (0..items.len()).map(|_| None).collect();
+
+    for (idx, result) in receiver.iter() {
+        results[idx] = Some(result);
+    }
+
+    results
+        .into_iter()
+        .map(|result| result.expect("every item is processed exactly once"))
+        .collect()
+}
@@ -0,0 +1,86 @@
+#![cfg(unix)]
+
+use blackd_client::{BlackError, BlackdClient, FormatOptions, FormatOutcome};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::thread;
+
+/// A stand-in for blackd that answers a single request by swapping single quotes for double
+/// quotes, handing back the request's head so the test can check what was sent
+fn serve_once(socket: &Path) -> thread::JoinHandle<String> {
+    let listener = UnixListener::bind(socket).unwrap();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+
+            if line.trim_end().is_empty() {
+                break;
+            }
+
+            head.push_str(&line);
+        }
+
+        let content_length = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                if name.eq_ignore_ascii_case("content-length") {
+                    value.trim().parse::<usize>().ok()
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0);
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let formatted = String::from_utf8(body).unwrap().replace('\'', "\"");
+
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            formatted.len(),
+            formatted
+        )
+        .unwrap();
+
+        head
+    })
+}
+
+#[test]
+fn formats_over_a_unix_socket() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("blackd.sock");
+    let server = serve_once(&socket);
+
+    let client = BlackdClient::new(format!("unix://{}", socket.display()));
+    let outcome = client
+        .format_str("x = 'a'\n", &FormatOptions::new().line_length(100))
+        .unwrap();
+
+    assert_eq!(outcome, FormatOutcome::Changed("x = \"a\"\n".to_string()));
+
+    let head = server.join().unwrap().to_ascii_lowercase();
+    assert!(head.starts_with("post / http/1.1"));
+    assert!(head.contains("x-line-length: 100"));
+}
+
+#[test]
+fn reports_the_socket_when_nothing_is_listening() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("unix://{}", dir.path().join("missing.sock").display());
+
+    match BlackdClient::new(url.as_str()).format_str("x = 1\n", &FormatOptions::new()) {
+        Err(BlackError::Connection { url: reported, .. }) => assert_eq!(reported, url),
+        other => panic!("expected a connection error, got {:?}", other),
+    }
+}